@@ -0,0 +1,567 @@
+//! `aiger::Aig`: And-Inverter Graphs, with AIGER import/export and BLIF export.
+
+use ::term::{RTerm, Term, TermType, TermGraph};
+use ::sha::Sha256;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+
+/// An And-Inverter Graph: Only two input `And` gates and inverted edges.
+///
+/// Literals follow the AIGER convention: `2 * variable + inverted`, with
+/// the literals 0 and 1 being the constants false and true. Variables
+/// `1..=nr_of_inputs()` are the primary inputs, the following ones the
+/// `And` gates in topological order.
+///
+/// `Xor` and `Or` `Term`s are mapped to `a | b = !(!a & !b)` and
+/// `a ^ b = !(!(a & !b) & !(!a & b))`. The gates are folded with constants
+/// and hashed while building, so equal gates are only added once.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Aig {
+    nr_of_inputs : u32,
+    /// `(lhs, rhs0, rhs1)` with `lhs > rhs0 >= rhs1`.
+    ands : Vec<(u32, u32, u32)>,
+    outputs : Vec<u32>,
+    input_names : Vec<String>,
+    output_names : Vec<String>,
+    unique : HashMap<(u32, u32), u32>,
+}
+
+/// An `And` gate `(lhs, rhs0, rhs1)`, as literals.
+type Gate = (u32, u32, u32);
+
+fn invalid(msg : &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+impl Aig {
+
+    /// Builds the AIG of `outputs`. The `Symbol`s in `inputs` become the first
+    /// primary inputs, other `Symbol`s the outputs depend on are appended.
+    pub fn from_terms(inputs : &[RTerm], outputs : &[RTerm]) -> Aig {
+        let mut aig = Aig::default();
+        TermGraph::with(|g| {
+            let needed = g.cone(outputs);
+            let mut lits : Vec<u32> = vec![0; needed.len()];
+            let mut input_of = HashMap::new();
+            let add_input = |aig : &mut Aig, t : RTerm| {
+                aig.nr_of_inputs += 1;
+                aig.input_names.push(match g.term_type(t) {
+                    TermType::Symbol(s) => format!("s{}", s),
+                    _ => panic!("Input is not a symbol."),
+                });
+                2 * aig.nr_of_inputs
+            };
+            for t in inputs {
+                let l = add_input(&mut aig, *t);
+                input_of.insert(*t, l);
+            }
+            for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+                lits[i] = match g.term_type(g.id(i)) {
+                    TermType::Symbol(_) => match input_of.get(&g.id(i)) {
+                        Some(l) => *l,
+                        None => add_input(&mut aig, g.id(i)),
+                    },
+                    TermType::Constant(c) => c as u32,
+                    TermType::Not(x) => lits[x.index()] ^ 1,
+                    TermType::And(x, y) => aig.and(lits[x.index()], lits[y.index()]),
+                    TermType::Or(x, y) => aig.and(lits[x.index()] ^ 1, lits[y.index()] ^ 1) ^ 1,
+                    TermType::Xor(x, y) => {
+                        let (a, b) = (lits[x.index()], lits[y.index()]);
+                        let p = aig.and(a, b ^ 1);
+                        let q = aig.and(a ^ 1, b);
+                        aig.and(p ^ 1, q ^ 1) ^ 1
+                    },
+                };
+            }
+            for (k, o) in outputs.iter().enumerate() {
+                aig.outputs.push(lits[o.index()]);
+                aig.output_names.push(format!("o{}", k));
+            }
+        });
+        aig
+    }
+
+    /// The AIG of the digest of `sha`. The inputs are the `Symbol`s in
+    /// `sha.data`, named `in[<position>]` with 0 being the MSBit of the first
+    /// byte. The outputs are the digest bits in the order of
+    /// `Sha256::evaluate()`, named `digest[<index>]`.
+    pub fn sha(sha : &Sha256) -> Aig {
+        let mut inputs = Vec::new();
+        let mut names = Vec::new();
+        for (w, u) in sha.data.iter().enumerate() {
+            for (j, b) in u.bits().iter().rev().enumerate() {
+                if let TermType::Symbol(_) = b.term_type() {
+                    inputs.push(*b);
+                    names.push(format!("in[{}]", w * 32 + j));
+                }
+            }
+        }
+        let outputs : Vec<RTerm> = sha.digest.iter().flat_map(|u| u.bits().iter().rev().cloned()).collect();
+        let mut aig = Aig::from_terms(&inputs, &outputs);
+        aig.input_names.splice(0..names.len(), names);
+        aig.output_names = (0..256).map(|i| format!("digest[{}]", i)).collect();
+        aig
+    }
+
+    /// Number of primary inputs.
+    pub fn nr_of_inputs(&self) -> usize {
+        self.nr_of_inputs as usize
+    }
+
+    /// Number of `And` gates.
+    pub fn nr_of_ands(&self) -> usize {
+        self.ands.len()
+    }
+
+    /// The literals of the outputs.
+    pub fn outputs(&self) -> &[u32] {
+        &self.outputs
+    }
+
+    fn max_var(&self) -> u32 {
+        self.nr_of_inputs + self.ands.len() as u32
+    }
+
+    /// Literal of `a & b`, adding a gate if needed.
+    fn and(&mut self, a : u32, b : u32) -> u32 {
+        let (a, b) = if a > b { (a, b) } else { (b, a) };
+        if b == 0 || a == b ^ 1 {
+            return 0;
+        }
+        if b == 1 || a == b {
+            return a;
+        }
+        if let Some(l) = self.unique.get(&(a, b)) {
+            return *l;
+        }
+        let lhs = 2 * (self.max_var() + 1);
+        self.ands.push((lhs, a, b));
+        self.unique.insert((a, b), lhs);
+        lhs
+    }
+
+    /// Creates `Term`s for the outputs, with the `Symbol`s (or any other
+    /// `Term`s) in `inputs` as primary inputs.
+    ///
+    /// Panics if `inputs` has not `nr_of_inputs()` elements.
+    pub fn to_terms(&self, inputs : &[RTerm]) -> Vec<RTerm> {
+        assert_eq!(inputs.len(), self.nr_of_inputs());
+        let mut terms = Vec::with_capacity(self.max_var() as usize + 1);
+        terms.push(Term::c0());
+        terms.extend_from_slice(inputs);
+        let lit = |terms : &Vec<RTerm>, l : u32| {
+            let t = terms[(l / 2) as usize];
+            if l & 1 == 1 { Term::not(&t) } else { t }
+        };
+        for &(_, a, b) in self.ands.iter() {
+            let t = Term::and(&lit(&terms, a), &lit(&terms, b));
+            terms.push(t);
+        }
+        self.outputs.iter().map(|l| lit(&terms, *l)).collect()
+    }
+
+    /// Writes the AIG in the ASCII AIGER format (`aag`), with a symbol table.
+    pub fn write_ascii<W : Write>(&self, out : &mut W) -> io::Result<()> {
+        writeln!(out, "aag {} {} 0 {} {}", self.max_var(), self.nr_of_inputs, self.outputs.len(), self.ands.len())?;
+        for i in 1..=self.nr_of_inputs {
+            writeln!(out, "{}", 2 * i)?;
+        }
+        for o in self.outputs.iter() {
+            writeln!(out, "{}", o)?;
+        }
+        for &(lhs, a, b) in self.ands.iter() {
+            writeln!(out, "{} {} {}", lhs, a, b)?;
+        }
+        self.write_symbols(out)
+    }
+
+    /// Writes the AIG in the binary AIGER format (`aig`), with a symbol table.
+    pub fn write_binary<W : Write>(&self, out : &mut W) -> io::Result<()> {
+        writeln!(out, "aig {} {} 0 {} {}", self.max_var(), self.nr_of_inputs, self.outputs.len(), self.ands.len())?;
+        for o in self.outputs.iter() {
+            writeln!(out, "{}", o)?;
+        }
+        for &(lhs, a, b) in self.ands.iter() {
+            write_delta(out, lhs - a)?;
+            write_delta(out, a - b)?;
+        }
+        self.write_symbols(out)
+    }
+
+    fn write_symbols<W : Write>(&self, out : &mut W) -> io::Result<()> {
+        for (i, n) in self.input_names.iter().enumerate() {
+            writeln!(out, "i{} {}", i, n)?;
+        }
+        for (i, n) in self.output_names.iter().enumerate() {
+            writeln!(out, "o{} {}", i, n)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the AIG as a BLIF model named `name`. Each `And` gate becomes
+    /// a `.names` table with its inversions folded into the cover. Constant
+    /// operands (only in AIGs from `read()`) use the constant 0 net `c0`.
+    pub fn write_blif<W : Write>(&self, out : &mut W, name : &str) -> io::Result<()> {
+        let signal = |l : u32| {
+            let v = l / 2;
+            if v == 0 {
+                "c0".to_string()
+            } else if v <= self.nr_of_inputs {
+                format!("i{}", v - 1)
+            } else {
+                format!("n{}", v)
+            }
+        };
+        let bit = |l : u32| if l & 1 == 1 { '0' } else { '1' };
+        writeln!(out, ".model {}", name)?;
+        write!(out, ".inputs")?;
+        for i in 0..self.nr_of_inputs {
+            write!(out, " i{}", i)?;
+        }
+        writeln!(out)?;
+        write!(out, ".outputs")?;
+        for k in 0..self.outputs.len() {
+            write!(out, " o{}", k)?;
+        }
+        writeln!(out)?;
+        if self.ands.iter().any(|&(_, _, b)| b < 2) {
+            writeln!(out, ".names c0")?;
+        }
+        for &(lhs, a, b) in self.ands.iter() {
+            writeln!(out, ".names {} {} {}", signal(a), signal(b), signal(lhs))?;
+            writeln!(out, "{}{} 1", bit(a), bit(b))?;
+        }
+        for (k, &o) in self.outputs.iter().enumerate() {
+            if o < 2 {
+                writeln!(out, ".names o{}", k)?;
+                if o == 1 {
+                    writeln!(out, "1")?;
+                }
+            } else {
+                writeln!(out, ".names {} o{}", signal(o), k)?;
+                writeln!(out, "{} 1", bit(o))?;
+            }
+        }
+        writeln!(out, ".end")
+    }
+
+    /// Reads an AIG in the ASCII or binary AIGER format. Latches are not supported.
+    ///
+    /// The variables of an ASCII file may be numbered in any order and the
+    /// `And` gates may be listed in any order, as long as they are acyclic.
+    /// They are renumbered: the inputs in their order, then the gates in a
+    /// topological order. Binary files are numbered densely by definition.
+    pub fn read<R : BufRead>(input : &mut R) -> io::Result<Aig> {
+        let mut header = String::new();
+        input.read_line(&mut header)?;
+        let fields : Vec<&str> = header.split_whitespace().collect();
+        if fields.len() != 6 || (fields[0] != "aag" && fields[0] != "aig") {
+            return Err(invalid("Not an AIGER header."));
+        }
+        let binary = fields[0] == "aig";
+        let nums : Vec<u32> = fields[1..].iter().map(|f| f.parse().map_err(|_| invalid("Bad number in header.")))
+                                         .collect::<io::Result<_>>()?;
+        let (max_var, nr_of_inputs, latches, nr_of_outputs, nr_of_ands) = (nums[0], nums[1], nums[2], nums[3], nums[4]);
+        if latches != 0 {
+            return Err(invalid("Latches are not supported."));
+        }
+        if max_var.checked_mul(2).and_then(|l| l.checked_add(1)).is_none() {
+            return Err(invalid("Too many variables."));
+        }
+        if binary && max_var != nr_of_inputs + nr_of_ands {
+            return Err(invalid("Variables are not numbered densely."));
+        }
+        let mut line = String::new();
+        let mut read_nums = |input : &mut R, n : usize| -> io::Result<Vec<u32>> {
+            line.clear();
+            input.read_line(&mut line)?;
+            let v : Vec<u32> = line.split_whitespace().map(|f| f.parse().map_err(|_| invalid("Bad literal.")))
+                                   .collect::<io::Result<_>>()?;
+            if v.len() != n { Err(invalid("Wrong number of literals.")) } else { Ok(v) }
+        };
+        let mut aig = Aig { nr_of_inputs, ..Aig::default() };
+        let mut inputs = Vec::new();
+        if !binary {
+            for _ in 0..nr_of_inputs {
+                inputs.push(read_nums(input, 1)?[0]);
+            }
+        }
+        let mut outputs = Vec::new();
+        for _ in 0..nr_of_outputs {
+            let o = read_nums(input, 1)?[0];
+            if o > 2 * max_var + 1 {
+                return Err(invalid("Output literal out of range."));
+            }
+            outputs.push(o);
+        }
+        if binary {
+            for k in 0..nr_of_ands {
+                let lhs = 2 * (nr_of_inputs + k + 1);
+                let a = lhs.checked_sub(read_delta(input)?);
+                let b = a.and_then(|a| read_delta(input).ok().and_then(|d| a.checked_sub(d)));
+                let (a, b) = match (a, b) {
+                    (Some(a), Some(b)) => (a, b),
+                    _ => return Err(invalid("Bad delta.")),
+                };
+                aig.ands.push((lhs, a.max(b), a.min(b)));
+            }
+            aig.outputs = outputs;
+        } else {
+            let mut gates = Vec::new();
+            for _ in 0..nr_of_ands {
+                let v = read_nums(input, 3)?;
+                gates.push((v[0], v[1], v[2]));
+            }
+            let (vars, ands) = renumber(max_var, &inputs, &gates)?;
+            aig.ands = ands;
+            for o in outputs {
+                match vars[(o / 2) as usize] {
+                    u32::MAX => return Err(invalid("Output of an undefined variable.")),
+                    v => aig.outputs.push(2 * v + (o & 1)),
+                }
+            }
+        }
+        for &(lhs, a, b) in aig.ands.iter() {
+            aig.unique.insert((a, b), lhs);
+        }
+        aig.input_names = (0..nr_of_inputs).map(|i| format!("i{}", i)).collect();
+        aig.output_names = (0..nr_of_outputs).map(|i| format!("o{}", i)).collect();
+        // Symbol table, up to the optional comment section
+        loop {
+            line.clear();
+            if input.read_line(&mut line)? == 0 || line.starts_with('c') {
+                break;
+            }
+            let mut parts = line.trim_end().splitn(2, ' ');
+            let (pos, name) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+            let index = pos.get(1..).and_then(|i| i.parse::<usize>().ok());
+            match (pos.chars().next(), index) {
+                (Some('i'), Some(i)) if i < aig.input_names.len() => aig.input_names[i] = name.to_string(),
+                (Some('o'), Some(i)) if i < aig.output_names.len() => aig.output_names[i] = name.to_string(),
+                _ => return Err(invalid("Bad symbol table entry.")),
+            }
+        }
+        Ok(aig)
+    }
+}
+
+/// Renumbers the variables of an ASCII AIG: The `inputs` become `1..=inputs.len()`,
+/// the `gates` (`(lhs, rhs0, rhs1)`) follow in a topological order.
+///
+/// Returns the new number of each old variable (`u32::MAX` if undefined) and
+/// the renumbered gates, like `Aig::ands`.
+fn renumber(max_var : u32, inputs : &[u32], gates : &[Gate]) -> io::Result<(Vec<u32>, Vec<Gate>)> {
+    let mut vars = vec![u32::MAX; max_var as usize + 1];
+    vars[0] = 0;
+    let mut gate_of = vec![usize::MAX; max_var as usize + 1];
+    let defined = |vars : &[u32], gate_of : &[usize], l : u32| {
+        let v = (l / 2) as usize;
+        vars[v] != u32::MAX || gate_of[v] != usize::MAX
+    };
+    for (i, &l) in inputs.iter().enumerate() {
+        if l & 1 == 1 || l < 2 || l / 2 > max_var || defined(&vars, &gate_of, l) {
+            return Err(invalid("Bad input literal."));
+        }
+        vars[(l / 2) as usize] = i as u32 + 1;
+    }
+    for (k, &(lhs, a, b)) in gates.iter().enumerate() {
+        if lhs & 1 == 1 || lhs < 2 || lhs / 2 > max_var || defined(&vars, &gate_of, lhs) {
+            return Err(invalid("Bad And gate literal."));
+        }
+        if a / 2 > max_var || b / 2 > max_var {
+            return Err(invalid("And gate literal out of range."));
+        }
+        gate_of[(lhs / 2) as usize] = k;
+    }
+    // Depth first, a gate gets its number after its operands.
+    let mut next = inputs.len() as u32 + 1;
+    let mut ands = Vec::with_capacity(gates.len());
+    let mut on_stack = vec![false; gates.len()];
+    for root in 0..gates.len() {
+        let mut stack = vec![root];
+        while let Some(&k) = stack.last() {
+            let (lhs, a, b) = gates[k];
+            if vars[(lhs / 2) as usize] != u32::MAX {
+                stack.pop();
+                continue;
+            }
+            on_stack[k] = true;
+            let mut ready = true;
+            for op in [a, b].iter() {
+                let v = (op / 2) as usize;
+                if vars[v] == u32::MAX {
+                    match gate_of[v] {
+                        usize::MAX => return Err(invalid("And gate of an undefined variable.")),
+                        g if on_stack[g] => return Err(invalid("And gates form a cycle.")),
+                        g => stack.push(g),
+                    }
+                    ready = false;
+                }
+            }
+            if ready {
+                stack.pop();
+                on_stack[k] = false;
+                vars[(lhs / 2) as usize] = next;
+                next += 1;
+                let lit = |l : u32| 2 * vars[(l / 2) as usize] + (l & 1);
+                let (a, b) = (lit(a), lit(b));
+                ands.push((lit(lhs), a.max(b), a.min(b)));
+            }
+        }
+    }
+    Ok((vars, ands))
+}
+
+fn write_delta<W : Write>(out : &mut W, mut x : u32) -> io::Result<()> {
+    while x & !0x7f != 0 {
+        out.write_all(&[(x & 0x7f) as u8 | 0x80])?;
+        x >>= 7;
+    }
+    out.write_all(&[x as u8])
+}
+
+fn read_delta<R : Read>(input : &mut R) -> io::Result<u32> {
+    let mut x = 0u32;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8];
+        input.read_exact(&mut byte)?;
+        if shift > 28 {
+            return Err(invalid("Delta too large."));
+        }
+        x |= ((byte[0] & 0x7f) as u32) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(x);
+        }
+        shift += 7;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::u::U;
+    use ::bitslice::BitSlice;
+    use std::io::Cursor;
+
+    #[test]
+    fn gates() {
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let outputs = [Term::xor(&a, &b), Term::or(&a, &b), Term::and(&a, &Term::not(&b)), Term::c1()];
+        let aig = Aig::from_terms(&[a, b], &outputs);
+        assert_eq!(aig.nr_of_inputs(), 2);
+        // a & !b is shared with the xor
+        assert_eq!(aig.nr_of_ands(), 4);
+        let mut out = Vec::new();
+        aig.write_ascii(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   format!("aag 6 2 0 4 4\n2\n4\n11\n13\n6\n1\n6 5 2\n8 4 3\n10 9 7\n12 5 3\ni0 s{}\ni1 s{}\no0 o0\no1 o1\no2 o2\no3 o3\n",
+                           sym(a), sym(b)));
+        for i in 0..4 {
+            a.set((i & 1) as f64);
+            b.set((i >> 1) as f64);
+            let imported = aig.to_terms(&[a, b]);
+            for (o, t) in outputs.iter().zip(imported.iter()) {
+                o.reset();
+                t.reset();
+                assert_eq!(o.evaluate(), t.evaluate());
+            }
+        }
+
+        let mut blif = Vec::new();
+        aig.write_blif(&mut blif, "gates").unwrap();
+        let blif = String::from_utf8(blif).unwrap();
+        assert!(blif.starts_with(".model gates\n.inputs i0 i1\n.outputs o0 o1 o2 o3\n"));
+        assert!(blif.contains(".names i1 i0 n3\n01 1\n"));
+        assert!(blif.contains(".names i1 i0 n6\n00 1\n"));
+        assert!(blif.contains(".names n5 o0\n0 1\n"));
+        assert!(blif.contains(".names o3\n1\n"));
+        assert!(blif.ends_with(".end\n"));
+    }
+
+    fn sym(t : RTerm) -> usize {
+        match t.term_type() { TermType::Symbol(s) => s, _ => unreachable!() }
+    }
+
+    #[test]
+    fn sha_round_trip() {
+        let data = vec![U::new_symbolic()];
+        let s = Sha256::new(data, 16);
+        let aig = Aig::sha(&s);
+        assert_eq!(aig.nr_of_inputs(), 16);
+        assert_eq!(aig.outputs().len(), 256);
+
+        let mut ascii = Vec::new();
+        aig.write_ascii(&mut ascii).unwrap();
+        let mut binary = Vec::new();
+        aig.write_binary(&mut binary).unwrap();
+        assert!(binary.len() < ascii.len());
+        let from_ascii = Aig::read(&mut Cursor::new(&ascii)).unwrap();
+        let from_binary = Aig::read(&mut Cursor::new(&binary)).unwrap();
+        assert_eq!(from_ascii, aig);
+        assert_eq!(from_binary, aig);
+
+        // Evaluate original and imported circuit for 64 messages
+        let inputs : Vec<RTerm> = s.data[0].bits().iter().rev().take(16).cloned().collect();
+        let imported = from_binary.to_terms(&inputs);
+        let mut bits = BitSlice::new();
+        for (i, t) in inputs.iter().enumerate() {
+            bits.set_word(*t, (i as u64 + 3).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        }
+        let outputs : Vec<RTerm> = s.digest.iter().flat_map(|u| u.bits().iter().rev().cloned()).collect();
+        TermGraph::with(|g| assert_eq!(g.evaluate_bits(&outputs, &bits), g.evaluate_bits(&imported, &bits)));
+    }
+
+    #[test]
+    fn bad_input() {
+        assert!(Aig::read(&mut Cursor::new("aag 1 1 1 0 0\n")).is_err());
+        assert!(Aig::read(&mut Cursor::new("aag 3 1 0 1 1\n2\n6\n6 8 2\n")).is_err());
+        assert!(Aig::read(&mut Cursor::new("hello")).is_err());
+        // Output literal 8 refers to the variable 4 > M
+        let err = Aig::read(&mut Cursor::new("aag 3 1 0 1 2\n2\n8\n4 2 1\n6 4 3\n")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = Aig::read(&mut Cursor::new("aig 1 1 0 1 0\n4\n")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn shuffled_ascii() {
+        // Inputs i0 = v2 and i1 = v1, v3 and v4 unused, the gates out of order:
+        // v7 = v5 & !v6, v5 = i0 & !i1, v6 = !i0 & i1
+        let aig = Aig::read(&mut Cursor::new("aag 7 2 0 2 3\n4\n2\n15\n12\n14 10 13\n10 4 3\n12 5 2\n")).unwrap();
+        assert_eq!((aig.nr_of_inputs(), aig.nr_of_ands()), (2, 3));
+        assert!(aig.ands.iter().all(|&(lhs, a, b)| lhs > a && a >= b));
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let outputs = aig.to_terms(&[a, b]);
+        for i in 0..4 {
+            let (x, y) = (i & 1 == 1, i >> 1 == 1);
+            a.set(x as u8 as f64);
+            b.set(y as u8 as f64);
+            let values : Vec<f64> = outputs.iter().map(|o| { o.reset(); o.evaluate() }).collect();
+            assert_eq!(values, vec![!(x & !y) as u8 as f64, (!x & y) as u8 as f64]);
+        }
+
+        // A cycle, an undefined operand and a variable defined twice
+        for text in ["aag 2 0 0 1 2\n2\n2 4 1\n4 2 1\n",
+                     "aag 3 1 0 1 1\n2\n4\n4 2 6\n",
+                     "aag 3 1 0 1 2\n2\n4\n4 2 1\n4 2 0\n"].iter() {
+            let err = Aig::read(&mut Cursor::new(text)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn constant_operands() {
+        // i0 & 1 and i0 & 0
+        let aig = Aig::read(&mut Cursor::new("aag 3 1 0 2 2\n2\n4\n6\n4 2 1\n6 2 0\n")).unwrap();
+        let mut blif = Vec::new();
+        aig.write_blif(&mut blif, "constants").unwrap();
+        let blif = String::from_utf8(blif).unwrap();
+        assert_eq!(blif.matches(".names c0\n").count(), 1);
+        assert!(blif.contains(".names i0 c0 n2\n10 1\n"));
+        assert!(blif.contains(".names i0 c0 n3\n11 1\n"));
+    }
+}
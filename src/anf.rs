@@ -0,0 +1,305 @@
+//! `anf::Anf`: Algebraic normal form of `Term`s, polynomials over GF(2).
+
+use ::term::{RTerm, TermType, TermGraph};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+/// A product of distinct `Symbol`s, given by their sorted symbol numbers.
+/// The empty product is the constant 1.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Monomial(Vec<usize>);
+
+/// A polynomial over GF(2) in the `Symbol`s, i.e. a xor of monomials.
+///
+/// Every boolean function has exactly one such algebraic normal form, so two
+/// `Anf`s are equal iff they describe the same function. It is built from
+/// `Term`s with `Xor` as addition, `And` as multiplication, `!a = a + 1` and
+/// `a | b = a + b + ab`, which may blow up exponentially.
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct Anf {
+    monomials : BTreeSet<Monomial>,
+}
+
+/// Sizes of a set of `Anf`s.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct AnfStatistics {
+    pub nr_of_polynomials : usize,
+    /// Highest degree of all polynomials.
+    pub max_degree : usize,
+    /// Sum of the degrees, divide by `nr_of_polynomials` for the mean.
+    pub sum_of_degrees : usize,
+    /// Number of monomials of the largest polynomial.
+    pub max_monomials : usize,
+    pub total_monomials : usize,
+}
+
+impl Monomial {
+    /// The symbol numbers, ascending.
+    pub fn symbols(&self) -> &[usize] {
+        &self.0
+    }
+
+    /// Number of symbols.
+    pub fn degree(&self) -> usize {
+        self.0.len()
+    }
+
+    fn mul(&self, other : &Monomial) -> Monomial {
+        let (a, b) = (&self.0, &other.0);
+        let mut m = Vec::with_capacity(a.len() + b.len());
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            if a[i] < b[j] {
+                m.push(a[i]);
+                i += 1;
+            } else if b[j] < a[i] {
+                m.push(b[j]);
+                j += 1;
+            } else {
+                m.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+        m.extend_from_slice(&a[i..]);
+        m.extend_from_slice(&b[j..]);
+        Monomial(m)
+    }
+}
+
+impl Anf {
+    /// The constant 0.
+    pub fn zero() -> Anf {
+        Anf::default()
+    }
+
+    /// The constant 1.
+    pub fn one() -> Anf {
+        Anf::constant(true)
+    }
+
+    /// The constant `c`.
+    pub fn constant(c : bool) -> Anf {
+        let mut a = Anf::zero();
+        if c {
+            a.monomials.insert(Monomial(Vec::new()));
+        }
+        a
+    }
+
+    /// The symbol with number `symbol`.
+    pub fn symbol(symbol : usize) -> Anf {
+        let mut a = Anf::zero();
+        a.monomials.insert(Monomial(vec![symbol]));
+        a
+    }
+
+    /// The monomials, in ascending order.
+    pub fn monomials(&self) -> impl Iterator<Item = &Monomial> {
+        self.monomials.iter()
+    }
+
+    /// Number of monomials.
+    pub fn nr_of_monomials(&self) -> usize {
+        self.monomials.len()
+    }
+
+    /// Highest degree of the monomials, 0 for constants.
+    pub fn degree(&self) -> usize {
+        self.monomials.iter().map(|m| m.degree()).max().unwrap_or(0)
+    }
+
+    /// True if this is a constant.
+    pub fn is_const(&self) -> bool {
+        self.degree() == 0
+    }
+
+    /// Sum over GF(2), `self ^ other`.
+    pub fn add(&self, other : &Anf) -> Anf {
+        Anf { monomials : self.monomials.symmetric_difference(&other.monomials).cloned().collect() }
+    }
+
+    /// Product over GF(2), `self & other`.
+    ///
+    /// Returns `None` as soon as the product can not have `max_monomials` or
+    /// less monomials any more, i.e. when there are not enough products left
+    /// to cancel the surplus.
+    pub fn mul(&self, other : &Anf, max_monomials : usize) -> Option<Anf> {
+        let mut odd = HashSet::new();
+        let mut remaining = self.monomials.len() * other.monomials.len();
+        for a in self.monomials.iter() {
+            for b in other.monomials.iter() {
+                let m = a.mul(b);
+                if !odd.remove(&m) {
+                    odd.insert(m);
+                }
+                remaining -= 1;
+                if odd.len() > max_monomials + remaining {
+                    return None;
+                }
+            }
+        }
+        Some(Anf { monomials : odd.into_iter().collect() })
+    }
+
+    /// Evaluates the polynomial. `symbol(s)` gives the value of symbol `s`.
+    pub fn evaluate<F>(&self, symbol : F) -> bool where F : Fn(usize) -> bool {
+        self.monomials.iter().filter(|m| m.0.iter().all(|s| symbol(*s))).count() % 2 == 1
+    }
+}
+
+impl fmt::Display for Anf {
+    /// Writes e.g. `s3*s5 + s7 + 1`, or `0`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.monomials.is_empty() {
+            return write!(f, "0");
+        }
+        // Higher degrees first, the constant last.
+        let mut monomials : Vec<&Monomial> = self.monomials.iter().collect();
+        monomials.sort_by(|a, b| b.degree().cmp(&a.degree()).then(a.cmp(b)));
+        for (i, m) in monomials.iter().enumerate() {
+            if i > 0 {
+                write!(f, " + ")?;
+            }
+            if m.0.is_empty() {
+                write!(f, "1")?;
+            }
+            for (j, s) in m.0.iter().enumerate() {
+                write!(f, "{}s{}", if j == 0 { "" } else { "*" }, s)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AnfStatistics {
+    /// Collects the statistics of `polynomials`.
+    pub fn new(polynomials : &[Anf]) -> AnfStatistics {
+        let mut s = AnfStatistics::default();
+        for p in polynomials {
+            let (degree, monomials) = (p.degree(), p.nr_of_monomials());
+            s.nr_of_polynomials += 1;
+            s.max_degree = s.max_degree.max(degree);
+            s.sum_of_degrees += degree;
+            s.max_monomials = s.max_monomials.max(monomials);
+            s.total_monomials += monomials;
+        }
+        s
+    }
+}
+
+impl fmt::Display for AnfStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Polynomials: {}\nMax degree: {}\nMean degree: {:.2}\nMax monomials: {}\nTotal monomials: {}",
+            self.nr_of_polynomials,
+            self.max_degree,
+            self.sum_of_degrees as f64 / self.nr_of_polynomials.max(1) as f64,
+            self.max_monomials,
+            self.total_monomials)
+    }
+}
+
+impl TermGraph {
+    /// Converts `roots` to their algebraic normal form in the `free` symbols.
+    /// All other symbols are replaced by the value they have been `set()` to,
+    /// rounded to 0 or 1.
+    ///
+    /// Returns `None` as soon as a `Term` on the way has more than
+    /// `max_monomials` monomials. Panics if a symbol that is not free is unset.
+    pub fn anf(&self, roots : &[RTerm], free : &[RTerm], max_monomials : usize) -> Option<Vec<Anf>> {
+        let free : HashSet<RTerm> = free.iter().cloned().collect();
+        let needed = self.cone(roots);
+        let mut anf : Vec<Option<Anf>> = vec![None; needed.len()];
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            let t = self.id(i);
+            let a = |x : RTerm| anf[x.index()].as_ref().unwrap();
+            let p = match self.term_type(t) {
+                TermType::Symbol(s) if free.contains(&t) => Anf::symbol(s),
+                TermType::Symbol(s) => Anf::constant(self.symbol_value(s).expect("Symbol not set.") >= 0.5),
+                TermType::Constant(c) => Anf::constant(c),
+                TermType::Xor(x, y) => a(x).add(a(y)),
+                TermType::And(x, y) => a(x).mul(a(y), max_monomials)?,
+                TermType::Or(x, y) => {
+                    // x + y may cancel up to that many monomials of x*y.
+                    let cap = max_monomials + a(x).nr_of_monomials() + a(y).nr_of_monomials();
+                    a(x).add(a(y)).add(&a(x).mul(a(y), cap)?)
+                },
+                TermType::Not(x) => a(x).add(&Anf::one()),
+            };
+            if p.nr_of_monomials() > max_monomials {
+                return None;
+            }
+            anf[i] = Some(p);
+        }
+        Some(roots.iter().map(|r| anf[r.index()].clone().unwrap()).collect())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::term::Term;
+    use ::u::U;
+    use ::sha::Sha256;
+
+    #[test]
+    fn gates() {
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let c = Term::symbol();
+        let x = Term::or(&Term::xor(&a, &b), &Term::not(&Term::and(&b, &c)));
+        let s = |t : RTerm| match t.term_type() { TermType::Symbol(s) => s, _ => unreachable!() };
+        let p = TermGraph::with(|g| g.anf(&[x], &[a, b, c], 100)).unwrap().pop().unwrap();
+        // (a + b) + (bc + 1) + (a + b)(bc + 1) = abc + 1
+        assert_eq!(p.degree(), 3);
+        assert_eq!(p.nr_of_monomials(), 2);
+        assert_eq!(format!("{}", p), format!("s{}*s{}*s{} + 1", s(a), s(b), s(c)));
+        for i in 0..8 {
+            let v = [i & 1 == 1, i >> 1 & 1 == 1, i >> 2 & 1 == 1];
+            let value = |n : usize| v[n - s(a)];
+            assert_eq!(p.evaluate(value), (v[0] ^ v[1]) | !(v[1] & v[2]));
+        }
+        // Fixing b = 0 leaves the constant 1.
+        b.set(0.);
+        let p = TermGraph::with(|g| g.anf(&[x], &[a, c], 100)).unwrap().pop().unwrap();
+        assert_eq!(p, Anf::one());
+        assert!(TermGraph::with(|g| g.anf(&[x], &[a, b, c], 1)).is_none());
+    }
+
+    #[test]
+    fn mul_with_cap() {
+        let sum = |s : &[usize]| s.iter().fold(Anf::zero(), |p, s| p.add(&Anf::symbol(*s)));
+        let (p, q) = (sum(&[0, 1, 2]), sum(&[3, 4]));
+        assert_eq!(p.mul(&q, 6).unwrap().nr_of_monomials(), 6);
+        assert!(p.mul(&q, 5).is_none());
+        // (s0 + 1)^2 = s0 + 1, the surplus on the way cancels.
+        let r = sum(&[0]).add(&Anf::one());
+        assert_eq!(r.mul(&r, 2), Some(r.clone()));
+        assert!(r.mul(&r, 1).is_none());
+    }
+
+    #[test]
+    fn reduced_round_sha() {
+        let data = vec![U::new_symbolic()];
+        data[0].set_bytes(b"ab\0\0");
+        let s = Sha256::with_rounds(data, 16, 4);
+        let free : Vec<RTerm> = s.data[0].byte_bits(1)[..5].to_vec();
+        let polynomials = s.anf(&free, 1 << 12).unwrap();
+        let stats = AnfStatistics::new(&polynomials);
+        println!("{}", stats);
+        assert_eq!(stats.nr_of_polynomials, 256);
+        assert!(stats.max_degree > 1 && stats.max_degree <= 5);
+
+        let digest = s.evaluate();
+        let value = |n : usize| TermGraph::with(|g| g.symbol_value(n)).unwrap() == 1.;
+        for (p, d) in polynomials.iter().zip(digest.iter()) {
+            assert_eq!(p.evaluate(value), *d == 1.);
+        }
+        // Too many monomials for 8 rounds of two bytes.
+        let data = vec![U::new_symbolic()];
+        let free : Vec<RTerm> = data[0].bits().iter().rev().take(16).cloned().collect();
+        let s = Sha256::with_rounds(data, 16, 8);
+        assert!(s.anf(&free, 1 << 10).is_none());
+    }
+}
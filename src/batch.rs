@@ -0,0 +1,203 @@
+//! `batch::Batch`: Evaluate many input assignments in one pass.
+
+use ::term::{RTerm, TermType, TermGraph};
+use ::semantics::FuzzySemantics;
+use ::u::U;
+use std::mem;
+
+/// Values of the `Symbol`s for `lanes()` independent evaluations.
+///
+/// Each symbol can be given one value per lane. Symbols without lane values
+/// use the value they have been `set()` to in every lane. Evaluating a graph
+/// with a `Batch` walks the graph once and computes every `Term` lane-wise,
+/// which is much faster than evaluating the graph once per assignment.
+pub struct Batch {
+    lanes : usize,
+    /// Indexed by the number of the symbol.
+    values : Vec<Option<Vec<f64>>>,
+}
+
+impl Batch {
+    /// Creates a batch with `lanes` lanes and no lane values yet.
+    pub fn new(lanes : usize) -> Batch {
+        Batch { lanes, values : Vec::new() }
+    }
+
+    /// Number of lanes.
+    pub fn lanes(&self) -> usize {
+        self.lanes
+    }
+
+    /// Values of the symbol with number `symbol`, if lane values have been set.
+    pub fn symbol_lanes(&self, symbol : usize) -> Option<&[f64]> {
+        self.values.get(symbol).and_then(|v| v.as_ref()).map(|v| &v[..])
+    }
+
+    /// Sets the value of the `Symbol` type term `t` in lane `lane`.
+    /// All other lanes of `t` that have not been set yet get `t`s current value,
+    /// or 0.0 if it has none.
+    ///
+    /// Panics if the type is not `Symbol`.
+    pub fn set(&mut self, t : RTerm, lane : usize, n : f64) {
+        assert!(lane < self.lanes);
+        let (s, default) = TermGraph::with(|g| {
+            match g.term_type(t) {
+                TermType::Symbol(s) => (s, g.symbol_value(s).unwrap_or(0.)),
+                _ => panic!("Called set on non-symbol"),
+            }
+        });
+        if self.values.len() <= s {
+            self.values.resize(s + 1, None);
+        }
+        let lanes = self.lanes;
+        self.values[s].get_or_insert_with(|| vec![default; lanes])[lane] = n;
+    }
+
+    /// Sets one byte of `u` in lane `lane`. See `U::set_byte()`.
+    pub fn set_byte(&mut self, u : &U, mut set_value : u8, bytenum : usize, lane : usize) {
+        for b in u.byte_bits(bytenum) {
+            self.set(*b, lane, (set_value & 1u8) as f64);
+            set_value >>= 1;
+        }
+    }
+
+    /// Sets all four bytes of `u` in lane `lane`. See `U::set_bytes()`.
+    pub fn set_bytes(&mut self, u : &U, bytes : &[u8], lane : usize) {
+        for (bytenum, b) in bytes.iter().take(4).enumerate() {
+            self.set_byte(u, *b, bytenum, lane);
+        }
+    }
+}
+
+impl TermGraph {
+
+    /// Evaluates `roots` in all lanes of `batch` with one walk over the graph.
+    ///
+    /// Returns the lanes of each root, `result[root][lane]`. Lane values of
+    /// `Term`s are dropped as soon as no other `Term` of the cone needs them,
+    /// so the memory used depends on the width, not the size, of the graph.
+    ///
+    /// This does not use or modify the eval cache.
+    pub fn evaluate_batch<S : FuzzySemantics + ?Sized>(&self, roots : &[RTerm], batch : &Batch, sem : &S) -> Vec<Vec<f64>> {
+        let n = batch.lanes();
+        let needed = self.cone(roots);
+
+        // Last term using each term, so its lanes can be freed afterwards.
+        let mut last_use = vec![0usize; needed.len()];
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            match self.term_type(self.id(i)) {
+                TermType::Xor(x, y) | TermType::And(x, y) | TermType::Or(x, y) => {
+                    last_use[x.index()] = i;
+                    last_use[y.index()] = i;
+                },
+                TermType::Not(x) => last_use[x.index()] = i,
+                TermType::Symbol(_) | TermType::Constant(_) => {},
+            }
+        }
+        for r in roots {
+            last_use[r.index()] = usize::MAX;
+        }
+
+        let mut lanes : Vec<Vec<f64>> = vec![Vec::new(); needed.len()];
+        let mut free : Vec<Vec<f64>> = Vec::new();
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            let mut out = free.pop().unwrap_or_default();
+            out.clear();
+            match self.term_type(self.id(i)) {
+                TermType::Symbol(s) => {
+                    match batch.symbol_lanes(s) {
+                        Some(v) => out.extend_from_slice(v),
+                        None => {
+                            let v = self.symbol_value(s).expect("Symbol not set. Eval failed.");
+                            out.resize(n, v);
+                        },
+                    }
+                },
+                TermType::Constant(c) => out.resize(n, if c { 1. } else { 0. }),
+                TermType::Xor(x, y) => {
+                    out.extend(lanes[x.index()].iter().zip(lanes[y.index()].iter()).map(|(a, b)| sem.xor(*a, *b)));
+                },
+                TermType::And(x, y) => {
+                    out.extend(lanes[x.index()].iter().zip(lanes[y.index()].iter()).map(|(a, b)| sem.and(*a, *b)));
+                },
+                TermType::Or(x, y) => {
+                    out.extend(lanes[x.index()].iter().zip(lanes[y.index()].iter()).map(|(a, b)| sem.or(*a, *b)));
+                },
+                TermType::Not(x) => {
+                    out.extend(lanes[x.index()].iter().map(|a| sem.not(*a)));
+                },
+            }
+            lanes[i] = out;
+            match self.term_type(self.id(i)) {
+                TermType::Xor(x, y) | TermType::And(x, y) | TermType::Or(x, y) => {
+                    for o in [x, y].iter() {
+                        if last_use[o.index()] == i {
+                            free.push(mem::take(&mut lanes[o.index()]));
+                        }
+                    }
+                },
+                TermType::Not(x) => {
+                    if last_use[x.index()] == i {
+                        free.push(mem::take(&mut lanes[x.index()]));
+                    }
+                },
+                TermType::Symbol(_) | TermType::Constant(_) => {},
+            }
+        }
+        roots.iter().map(|r| lanes[r.index()].clone()).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::u::U;
+    use ::sha::Sha256;
+    use ::util::hex;
+
+    #[test]
+    fn batch_of_messages() {
+        let data = vec![U::new_symbolic()];
+        let messages = [b"a\n", b"ab", b"zz"];
+        let mut batch = Batch::new(messages.len());
+        for (lane, m) in messages.iter().enumerate() {
+            batch.set_byte(&data[0], m[0], 0, lane);
+            batch.set_byte(&data[0], m[1], 1, lane);
+        }
+        let s = Sha256::new(data, 16);
+        let digests = s.eval_to_u32_batch(&batch);
+        assert_eq!(digests.len(), 3);
+        // echo -n 'a\n', 'ab', 'zz' | sha256sum
+        assert_eq!(&hex(&digests[0]), "87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7");
+        assert_eq!(&hex(&digests[1]), "fb8e20fc2e4c3f248c60c39bd652f3c1347298bb977b8b4d5903b85055620603");
+        assert_eq!(&hex(&digests[2]), "4a60bf7d4bc1e485744cf7e8d0860524752fca1ce42331be7c439fd23043f151");
+    }
+
+    #[test]
+    fn lanes_match_scalar_evaluation() {
+        let u1 = U::new_symbolic();
+        let u2 = U::new_symbolic();
+        for (i, b) in u1.bits().iter().chain(u2.bits().iter()).enumerate() {
+            b.set((i % 5) as f64 / 4.);
+        }
+        let mut batch = Batch::new(4);
+        for lane in 0..4 {
+            for (i, b) in u1.bits().iter().enumerate() {
+                batch.set(*b, lane, ((i + lane) % 3) as f64 / 2.);
+            }
+        }
+        let sum = (&u1 + &u2) ^ u1.rotate_right(7);
+        let roots : Vec<RTerm> = sum.bits().to_vec();
+        let lanes = TermGraph::with(|g| g.evaluate_batch(&roots, &batch, &::semantics::Standard));
+        for lane in 0..4 {
+            for (i, b) in u1.bits().iter().enumerate() {
+                b.set(((i + lane) % 3) as f64 / 2.);
+            }
+            sum.reset();
+            for (r, l) in roots.iter().zip(lanes.iter()) {
+                assert_eq!(r.evaluate(), l[lane]);
+            }
+        }
+    }
+}
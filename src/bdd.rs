@@ -0,0 +1,376 @@
+//! `bdd::Bdd`: Reduced ordered binary decision diagrams of `Term`s.
+
+use ::term::{RTerm, TermType, TermGraph};
+use ::u::U;
+use std::collections::HashMap;
+
+/// Index of a node within its `Bdd`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct BddId(u32);
+
+/// The terminal node of the constant false function.
+pub const FALSE : BddId = BddId(0);
+/// The terminal node of the constant true function.
+pub const TRUE : BddId = BddId(1);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Op {
+    And,
+    Or,
+    Xor,
+}
+
+/// A reduced ordered BDD manager for some `Symbol`s as variables.
+///
+/// The variables are tested in the given order, the first one at the root.
+/// Nodes are shared between all functions built by one `Bdd`, so equal
+/// functions have the same `BddId`. The size of a BDD depends heavily on the
+/// variable order and explodes for most functions of many variables (e.g. a
+/// few rounds of SHA-256), so the number of nodes is limited by a budget and
+/// building fails cleanly when it is exhausted.
+pub struct Bdd {
+    /// `(level, low, high)`, where `low` is the cofactor for variable
+    /// `level` being 0. The terminals have level `u32::MAX`.
+    nodes : Vec<(u32, BddId, BddId)>,
+    unique : HashMap<(u32, BddId, BddId), BddId>,
+    computed : HashMap<(Op, BddId, BddId), BddId>,
+    /// Level of each variable symbol, by symbol number.
+    levels : HashMap<usize, u32>,
+    order : Vec<RTerm>,
+    max_nodes : usize,
+}
+
+impl BddId {
+    /// Position of the node in its `Bdd`.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Bdd {
+
+    /// A manager for the variables `order` (which must be `Symbol`s), with
+    /// at most `max_nodes` nodes including the terminals.
+    pub fn new(order : &[RTerm], max_nodes : usize) -> Bdd {
+        let levels = order.iter().enumerate().map(|(l, t)| match t.term_type() {
+            TermType::Symbol(s) => (s, l as u32),
+            _ => panic!("Variable is not a symbol."),
+        }).collect();
+        Bdd {
+            nodes : vec![(u32::MAX, FALSE, FALSE), (u32::MAX, TRUE, TRUE)],
+            unique : HashMap::new(),
+            computed : HashMap::new(),
+            levels,
+            order : order.to_vec(),
+            max_nodes : max_nodes.max(2),
+        }
+    }
+
+    /// The `Symbol`s of `data` in message bit order: MSBit of the first byte first.
+    pub fn message_order(data : &[U]) -> Vec<RTerm> {
+        data.iter().flat_map(|u| u.bits().iter().rev())
+            .filter(|b| matches!(b.term_type(), TermType::Symbol(_)))
+            .cloned().collect()
+    }
+
+    /// The variables, in order.
+    pub fn order(&self) -> &[RTerm] {
+        &self.order
+    }
+
+    /// Number of nodes of all functions, including the terminals.
+    pub fn nr_of_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Number of nodes of `f`, including the terminals it reaches.
+    pub fn size(&self, f : BddId) -> usize {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut stack = vec![f];
+        let mut size = 0;
+        while let Some(n) = stack.pop() {
+            if seen[n.index()] {
+                continue;
+            }
+            seen[n.index()] = true;
+            size += 1;
+            if n > TRUE {
+                let (_, lo, hi) = self.nodes[n.index()];
+                stack.push(lo);
+                stack.push(hi);
+            }
+        }
+        size
+    }
+
+    /// The function of variable `level`.
+    pub fn var(&mut self, level : usize) -> Option<BddId> {
+        assert!(level < self.order.len());
+        self.mk(level as u32, FALSE, TRUE)
+    }
+
+    /// Returns the node testing `level`, with the reduction rules applied.
+    /// `None` if the budget is exhausted.
+    fn mk(&mut self, level : u32, lo : BddId, hi : BddId) -> Option<BddId> {
+        if lo == hi {
+            return Some(lo);
+        }
+        if let Some(&n) = self.unique.get(&(level, lo, hi)) {
+            return Some(n);
+        }
+        if self.nodes.len() >= self.max_nodes {
+            return None;
+        }
+        let n = BddId(self.nodes.len() as u32);
+        self.nodes.push((level, lo, hi));
+        self.unique.insert((level, lo, hi), n);
+        Some(n)
+    }
+
+    fn level(&self, f : BddId) -> u32 {
+        self.nodes[f.index()].0
+    }
+
+    /// The cofactors of `f` for variable `level` being 0 and 1.
+    fn cofactors(&self, f : BddId, level : u32) -> (BddId, BddId) {
+        let (l, lo, hi) = self.nodes[f.index()];
+        if l == level { (lo, hi) } else { (f, f) }
+    }
+
+    fn apply(&mut self, op : Op, a : BddId, b : BddId) -> Option<BddId> {
+        let terminal = match op {
+            Op::And if a == FALSE || b == FALSE => Some(FALSE),
+            Op::And if a == TRUE => Some(b),
+            Op::And if b == TRUE || a == b => Some(a),
+            Op::Or if a == TRUE || b == TRUE => Some(TRUE),
+            Op::Or if a == FALSE => Some(b),
+            Op::Or if b == FALSE || a == b => Some(a),
+            Op::Xor if a == b => Some(FALSE),
+            Op::Xor if a == FALSE => Some(b),
+            Op::Xor if b == FALSE => Some(a),
+            _ => None,
+        };
+        if terminal.is_some() {
+            return terminal;
+        }
+        // All operations are commutative.
+        let (a, b) = if b < a { (b, a) } else { (a, b) };
+        if let Some(&r) = self.computed.get(&(op, a, b)) {
+            return Some(r);
+        }
+        let level = self.level(a).min(self.level(b));
+        let (a0, a1) = self.cofactors(a, level);
+        let (b0, b1) = self.cofactors(b, level);
+        let lo = self.apply(op, a0, b0)?;
+        let hi = self.apply(op, a1, b1)?;
+        let r = self.mk(level, lo, hi)?;
+        self.computed.insert((op, a, b), r);
+        Some(r)
+    }
+
+    /// `a & b`, `None` if the budget is exhausted.
+    pub fn and(&mut self, a : BddId, b : BddId) -> Option<BddId> {
+        self.apply(Op::And, a, b)
+    }
+
+    /// `a | b`, `None` if the budget is exhausted.
+    pub fn or(&mut self, a : BddId, b : BddId) -> Option<BddId> {
+        self.apply(Op::Or, a, b)
+    }
+
+    /// `a ^ b`, `None` if the budget is exhausted.
+    pub fn xor(&mut self, a : BddId, b : BddId) -> Option<BddId> {
+        self.apply(Op::Xor, a, b)
+    }
+
+    /// `!a`, `None` if the budget is exhausted.
+    pub fn not(&mut self, a : BddId) -> Option<BddId> {
+        self.apply(Op::Xor, a, TRUE)
+    }
+
+    /// Builds the BDDs of `roots`. Symbols that are not variables are replaced
+    /// by the value they have been `set()` to, rounded to 0 or 1.
+    ///
+    /// Returns `None` if the node budget is exhausted. Panics if a symbol that
+    /// is not a variable is unset.
+    pub fn build(&mut self, roots : &[RTerm]) -> Option<Vec<BddId>> {
+        let (needed, types, values) = TermGraph::with(|g| {
+            let needed = g.cone(roots);
+            let types : Vec<TermType> = (0..needed.len()).map(|i| g.term_type(g.id(i))).collect();
+            let values : HashMap<usize, bool> = types.iter().enumerate().filter(|&(i, _)| needed[i])
+                .filter_map(|(_, t)| match *t {
+                    TermType::Symbol(s) if !self.levels.contains_key(&s) =>
+                        Some((s, g.symbol_value(s).expect("Symbol not set.") >= 0.5)),
+                    _ => None,
+                }).collect();
+            (needed, types, values)
+        });
+        let mut bdd = vec![FALSE; needed.len()];
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            let b = |x : RTerm| bdd[x.index()];
+            bdd[i] = match types[i] {
+                TermType::Symbol(s) => match self.levels.get(&s) {
+                    Some(&l) => self.var(l as usize)?,
+                    None => if values[&s] { TRUE } else { FALSE },
+                },
+                TermType::Constant(c) => if c { TRUE } else { FALSE },
+                TermType::Xor(x, y) => { let (x, y) = (b(x), b(y)); self.xor(x, y)? },
+                TermType::And(x, y) => { let (x, y) = (b(x), b(y)); self.and(x, y)? },
+                TermType::Or(x, y) => { let (x, y) = (b(x), b(y)); self.or(x, y)? },
+                TermType::Not(x) => { let x = b(x); self.not(x)? },
+            };
+        }
+        Some(roots.iter().map(|r| bdd[r.index()]).collect())
+    }
+
+    /// Exact probability of `f` being 1 if variable `level` is 1 with
+    /// probability `p[level]`, independently of the others.
+    pub fn probability(&self, f : BddId, p : &[f64]) -> f64 {
+        assert_eq!(p.len(), self.order.len());
+        let mut memo = HashMap::new();
+        self.probability_rec(f, p, &mut memo)
+    }
+
+    fn probability_rec(&self, f : BddId, p : &[f64], memo : &mut HashMap<BddId, f64>) -> f64 {
+        if f == FALSE {
+            return 0.;
+        }
+        if f == TRUE {
+            return 1.;
+        }
+        if let Some(&v) = memo.get(&f) {
+            return v;
+        }
+        let (level, lo, hi) = self.nodes[f.index()];
+        let pv = p[level as usize];
+        let v = (1. - pv) * self.probability_rec(lo, p, memo) + pv * self.probability_rec(hi, p, memo);
+        memo.insert(f, v);
+        v
+    }
+
+    /// The values the variables have been `set()` to, as probabilities for
+    /// `probability()`. Panics if a variable is unset.
+    pub fn symbol_probabilities(&self) -> Vec<f64> {
+        TermGraph::with(|g| self.order.iter().map(|t| match g.term_type(*t) {
+            TermType::Symbol(s) => g.symbol_value(s).expect("Symbol not set."),
+            _ => unreachable!(),
+        }).collect())
+    }
+
+    /// Number of assignments to all variables that satisfy `f`.
+    /// Exact up to 2^53.
+    pub fn sat_count(&self, f : BddId) -> f64 {
+        self.probability(f, &vec![0.5; self.order.len()]) * 2f64.powi(self.order.len() as i32)
+    }
+
+    /// One satisfying assignment of `f`, indexed by level. Variables that do
+    /// not matter are `None`. Returns `None` if `f` is unsatisfiable.
+    pub fn any_sat(&self, f : BddId) -> Option<Vec<Option<bool>>> {
+        if f == FALSE {
+            return None;
+        }
+        let mut assignment = vec![None; self.order.len()];
+        let mut n = f;
+        // In a reduced BDD, every node except FALSE reaches TRUE.
+        while n != TRUE {
+            let (level, lo, hi) = self.nodes[n.index()];
+            if lo != FALSE {
+                assignment[level as usize] = Some(false);
+                n = lo;
+            } else {
+                assignment[level as usize] = Some(true);
+                n = hi;
+            }
+        }
+        Some(assignment)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::term::Term;
+    use ::sha::Sha256;
+    use ::bitslice::BitSlice;
+
+    #[test]
+    fn gates() {
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let c = Term::symbol();
+        let x = Term::or(&Term::xor(&a, &b), &Term::and(&b, &c));
+        let mut bdd = Bdd::new(&[a, b, c], 100);
+        let f = bdd.build(&[x]).unwrap()[0];
+        // a^b | bc is 1 for 010, 100, 101, 011, 111 (abc)
+        assert_eq!(bdd.sat_count(f), 5.);
+        assert_eq!(bdd.probability(f, &[0.5, 0.5, 0.5]), 5. / 8.);
+        let p = [0.1, 0.7, 0.4];
+        let exact = p[0] * (1. - p[1]) + (1. - p[0]) * p[1] + p[0] * p[1] * p[2];
+        assert!((bdd.probability(f, &p) - exact).abs() < 1e-12);
+        let sat = bdd.any_sat(f).unwrap();
+        assert_eq!(sat, vec![Some(false), Some(true), None]);
+        let nf = bdd.not(f).unwrap();
+        assert_eq!(bdd.sat_count(nf), 3.);
+        assert_eq!(bdd.and(f, nf), Some(FALSE));
+        assert_eq!(bdd.xor(f, nf), Some(TRUE));
+        assert!(bdd.any_sat(FALSE).is_none());
+
+        // Same function from other terms is the same node.
+        let y = Term::not(&Term::and(&Term::not(&Term::xor(&a, &b)), &Term::not(&Term::and(&b, &c))));
+        assert_eq!(bdd.build(&[y]).unwrap()[0], f);
+    }
+
+    #[test]
+    fn reduced_round_sha() {
+        let data = vec![U::new_symbolic()];
+        data[0].set_bytes(b"ab\0\0");
+        let s = Sha256::with_rounds(data, 16, 3);
+        let order = Bdd::message_order(&s.data);
+        assert_eq!(order.len(), 16);
+        // Byte 'b' is free.
+        let mut bdd = Bdd::new(&order[8..], 1 << 16);
+        let roots : Vec<RTerm> = s.digest.iter().flat_map(|u| u.bits().iter().rev().cloned()).collect();
+        let f = bdd.build(&roots).unwrap();
+
+        // Brute force all 256 assignments, assignment `a` in lane `a % 64` of `words[a / 64]`.
+        let words : Vec<Vec<u64>> = (0..4u64).map(|quarter| {
+            let mut bits = BitSlice::new();
+            for (i, t) in order[8..].iter().enumerate() {
+                let word = (0..64u64).fold(0u64, |w, lane| w | (((quarter * 64 + lane) >> (7 - i) & 1) << lane));
+                bits.set_word(*t, word);
+            }
+            TermGraph::with(|g| g.evaluate_bits(&roots, &bits))
+        }).collect();
+        let bit = |root : usize, a : usize| words[a / 64][root] >> (a % 64) & 1 == 1;
+        // Each message bit is 1 with probability 0.25.
+        let weight = |a : usize| 0.25f64.powi(a.count_ones() as i32) * 0.75f64.powi(8 - a.count_ones() as i32);
+        for t in order[8..].iter() {
+            t.set(0.25);
+        }
+        let p = bdd.symbol_probabilities();
+        for (root, f) in f.iter().enumerate() {
+            let ones = (0..256).filter(|a| bit(root, *a)).count();
+            assert_eq!(bdd.sat_count(*f), ones as f64);
+            let exact : f64 = (0..256).filter(|a| bit(root, *a)).map(weight).sum();
+            assert!((bdd.probability(*f, &p) - exact).abs() < 1e-12);
+            match bdd.any_sat(*f) {
+                Some(sat) => {
+                    let a = sat.iter().enumerate().fold(0, |a, (i, b)| a | ((*b == Some(true)) as usize) << (7 - i));
+                    assert!(bit(root, a));
+                },
+                None => assert_eq!(ones, 0),
+            }
+        }
+    }
+
+    #[test]
+    fn node_budget() {
+        let data = vec![U::new_symbolic()];
+        let s = Sha256::new(data, 16);
+        let mut bdd = Bdd::new(&Bdd::message_order(&s.data), 10_000);
+        let roots : Vec<RTerm> = s.digest[0].bits().to_vec();
+        assert!(bdd.build(&roots).is_none());
+        assert!(bdd.nr_of_nodes() <= 10_000);
+    }
+}
@@ -0,0 +1,256 @@
+//! `bitslice::BitSlice`: Exact boolean evaluation of 64 inputs at once.
+
+use ::term::{RTerm, TermType, TermGraph};
+use ::u::U;
+
+/// Lane `j` of free bit `i < 6` when enumerating all assignments, see `brute_force()`.
+const LANE_PATTERNS : [u64; 6] = [
+    0xAAAA_AAAA_AAAA_AAAA,
+    0xCCCC_CCCC_CCCC_CCCC,
+    0xF0F0_F0F0_F0F0_F0F0,
+    0xFF00_FF00_FF00_FF00,
+    0xFFFF_0000_FFFF_0000,
+    0xFFFF_FFFF_0000_0000,
+];
+
+/// Boolean values of the `Symbol`s for 64 independent evaluations.
+///
+/// Bit `j` of a word is the value in lane `j`. Evaluating a graph with a
+/// `BitSlice` computes each `Term` with one native bit operation on `u64`s,
+/// so one walk over the graph yields 64 real (non fuzzy) results. Symbols
+/// without lane values use the value they have been `set()` to in every lane,
+/// which must then be exactly 0.0 or 1.0.
+#[derive(Clone, Default)]
+pub struct BitSlice {
+    /// Indexed by the number of the symbol.
+    words : Vec<Option<u64>>,
+}
+
+impl BitSlice {
+    /// Number of lanes.
+    pub const LANES : usize = 64;
+
+    /// Creates a bit slice without lane values.
+    pub fn new() -> BitSlice {
+        BitSlice::default()
+    }
+
+    /// Values of the symbol with number `symbol` in all lanes, if they have been set.
+    pub fn symbol_word(&self, symbol : usize) -> Option<u64> {
+        self.words.get(symbol).and_then(|w| *w)
+    }
+
+    /// Sets the value of the `Symbol` type term `t` in all lanes at once.
+    ///
+    /// Panics if the type is not `Symbol`.
+    pub fn set_word(&mut self, t : RTerm, word : u64) {
+        let s = TermGraph::with(|g| symbol_nr(g, t));
+        if self.words.len() <= s {
+            self.words.resize(s + 1, None);
+        }
+        self.words[s] = Some(word);
+    }
+
+    /// Sets the value of the `Symbol` type term `t` in lane `lane`.
+    /// All other lanes of `t` that have not been set yet get `t`s current value,
+    /// or 0 if it has none.
+    ///
+    /// Panics if the type is not `Symbol`.
+    pub fn set(&mut self, t : RTerm, lane : usize, value : bool) {
+        assert!(lane < BitSlice::LANES);
+        let (s, default) = TermGraph::with(|g| {
+            let s = symbol_nr(g, t);
+            (s, g.symbol_value(s).map(|v| broadcast(to_bool(v))).unwrap_or(0))
+        });
+        if self.words.len() <= s {
+            self.words.resize(s + 1, None);
+        }
+        let w = self.words[s].get_or_insert(default);
+        if value {
+            *w |= 1 << lane;
+        } else {
+            *w &= !(1 << lane);
+        }
+    }
+
+    /// Sets one byte of `u` in lane `lane`. See `U::set_byte()`.
+    pub fn set_byte(&mut self, u : &U, mut set_value : u8, bytenum : usize, lane : usize) {
+        for b in u.byte_bits(bytenum) {
+            self.set(*b, lane, set_value & 1u8 != 0);
+            set_value >>= 1;
+        }
+    }
+
+    /// Sets all four bytes of `u` in lane `lane`. See `U::set_bytes()`.
+    pub fn set_bytes(&mut self, u : &U, bytes : &[u8], lane : usize) {
+        for (bytenum, b) in bytes.iter().take(4).enumerate() {
+            self.set_byte(u, *b, bytenum, lane);
+        }
+    }
+}
+
+fn symbol_nr(g : &TermGraph, t : RTerm) -> usize {
+    match g.term_type(t) {
+        TermType::Symbol(s) => s,
+        _ => panic!("Called set on non-symbol"),
+    }
+}
+
+fn broadcast(b : bool) -> u64 {
+    if b { !0 } else { 0 }
+}
+
+fn to_bool(v : f64) -> bool {
+    if v == 0. {
+        false
+    } else if v == 1. {
+        true
+    } else {
+        panic!("Symbol value {} is not boolean.", v)
+    }
+}
+
+impl TermGraph {
+
+    /// Evaluates `roots` in all 64 lanes of `bits` with one walk over the graph.
+    ///
+    /// Returns one word per root. Bit `j` of a word is the value of the root in lane `j`.
+    ///
+    /// This does not use or modify the eval cache.
+    ///
+    /// Panics if a symbol without lane values is unset or not exactly 0.0 or 1.0.
+    pub fn evaluate_bits(&self, roots : &[RTerm], bits : &BitSlice) -> Vec<u64> {
+        let needed = self.cone(roots);
+        let mut words = vec![0u64; needed.len()];
+        self.evaluate_bits_in(&needed, &mut words, |s| bits.symbol_word(s));
+        roots.iter().map(|r| words[r.index()]).collect()
+    }
+
+    /// Searches all assignments to the `free` symbols for one that makes
+    /// `roots[i]` equal to `target[i]` for all `i`. All other symbols keep
+    /// the value they have been `set()` to, which must be exactly 0.0 or 1.0.
+    ///
+    /// 64 assignments are tried per walk over the graph. Returns the first
+    /// assignment found, with bit `i` being the value of `free[i]`.
+    ///
+    /// Panics if there are more than 63 `free` symbols or if a `free` term
+    /// is not of type `Symbol`.
+    pub fn brute_force(&self, roots : &[RTerm], target : &[bool], free : &[RTerm]) -> Option<u64> {
+        assert_eq!(roots.len(), target.len());
+        assert!(free.len() < 64);
+        let mut lanes_of = vec![None; self.nr_of_symbols()];
+        for (i, t) in free.iter().enumerate() {
+            lanes_of[symbol_nr(self, *t)] = Some(i);
+        }
+        let needed = self.cone(roots);
+        let mut words = vec![0u64; needed.len()];
+        let total = 1u64 << free.len();
+        let mut base = 0u64;
+        while base < total {
+            self.evaluate_bits_in(&needed, &mut words, |s| {
+                lanes_of[s].map(|i| {
+                    if i < LANE_PATTERNS.len() { LANE_PATTERNS[i] } else { broadcast(base >> i & 1 == 1) }
+                })
+            });
+            let mut matching = if total - base < 64 { (1 << (total - base)) - 1 } else { !0 };
+            for (r, t) in roots.iter().zip(target.iter()) {
+                matching &= !(words[r.index()] ^ broadcast(*t));
+            }
+            if matching != 0 {
+                return Some(base + matching.trailing_zeros() as u64);
+            }
+            base += 64;
+        }
+        None
+    }
+
+    /// Evaluates the `needed` terms into `words`. `lanes(s)` gives the
+    /// words of the symbols that do not use their scalar value.
+    fn evaluate_bits_in<F>(&self, needed : &[bool], words : &mut [u64], lanes : F)
+        where F : Fn(usize) -> Option<u64> {
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            words[i] = match self.term_type(self.id(i)) {
+                TermType::Symbol(s) => {
+                    lanes(s).unwrap_or_else(|| {
+                        broadcast(to_bool(self.symbol_value(s).expect("Symbol not set. Eval failed.")))
+                    })
+                },
+                TermType::Constant(c) => broadcast(c),
+                TermType::Xor(x, y) => words[x.index()] ^ words[y.index()],
+                TermType::And(x, y) => words[x.index()] & words[y.index()],
+                TermType::Or(x, y) => words[x.index()] | words[y.index()],
+                TermType::Not(x) => !words[x.index()],
+            };
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::sha::Sha256;
+    use ::semantics::{FuzzySemantics, Standard, Probabilistic, Lukasiewicz, Goedel};
+    use ::util::hex;
+
+    #[test]
+    fn bits_of_messages() {
+        let data = vec![U::new_symbolic()];
+        let mut bits = BitSlice::new();
+        let messages = [b"a\n", b"ab", b"zz"];
+        for (lane, m) in messages.iter().enumerate() {
+            bits.set_bytes(&data[0], &m[..], lane);
+        }
+        let s = Sha256::new(data, 16);
+        let digests = s.eval_to_u32_bits(&bits);
+        assert_eq!(digests.len(), 64);
+        // echo -n 'a\n', 'ab', 'zz' | sha256sum
+        assert_eq!(&hex(&digests[0]), "87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7");
+        assert_eq!(&hex(&digests[1]), "fb8e20fc2e4c3f248c60c39bd652f3c1347298bb977b8b4d5903b85055620603");
+        assert_eq!(&hex(&digests[2]), "4a60bf7d4bc1e485744cf7e8d0860524752fca1ce42331be7c439fd23043f151");
+    }
+
+    #[test]
+    fn fuzzy_agrees_at_the_corners() {
+        let u1 = U::new_symbolic();
+        let u2 = U::new_symbolic();
+        let sum = (&u1 + &u2) ^ (u1.rotate_right(3).and(&u2.not()));
+        let roots : Vec<RTerm> = sum.bits().to_vec();
+        let inputs : Vec<RTerm> = u1.bits().iter().chain(u2.bits().iter()).cloned().collect();
+
+        // Some arbitrary, but different, lane patterns.
+        let patterns : Vec<u64> = (0..inputs.len() as u64).map(|i| (i + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15).rotate_left(i as u32)).collect();
+        let mut bits = BitSlice::new();
+        for (b, w) in inputs.iter().zip(patterns.iter()) {
+            bits.set_word(*b, *w);
+        }
+        let words = TermGraph::with(|g| g.evaluate_bits(&roots, &bits));
+
+        let sems : [&dyn FuzzySemantics; 4] = [&Standard, &Probabilistic, &Lukasiewicz, &Goedel];
+        for lane in 0..BitSlice::LANES {
+            for (b, w) in inputs.iter().zip(patterns.iter()) {
+                b.set((w >> lane & 1) as f64);
+            }
+            for sem in sems.iter() {
+                sum.reset();
+                for (r, w) in roots.iter().zip(words.iter()) {
+                    assert_eq!(r.evaluate_with(*sem), (w >> lane & 1) as f64);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn brute_force_last_byte() {
+        let data = vec![U::new_symbolic()];
+        data[0].set_byte(b'a', 0);
+        data[0].set_byte(0, 1);
+        let free = data[0].byte_bits(1).to_vec();
+        let s = Sha256::new(data, 16);
+        // First 32 bits of sha256('a\n')
+        let target : Vec<bool> = (0..32).rev().map(|i| 0x87428fc5u32 >> i & 1 == 1).collect();
+        assert_eq!(s.brute_force(&free, &target), Some(b'\n' as u64));
+        let unreachable : Vec<bool> = (0..32).map(|_| true).collect();
+        assert_eq!(s.brute_force(&free[..3], &unreachable), None);
+    }
+}
@@ -0,0 +1,325 @@
+//! `cnf::Cnf`: Tseitin encoding of `Term` graphs into DIMACS CNF.
+
+use ::term::{RTerm, TermType, TermGraph};
+use ::sha::Sha256;
+use ::util::{dehex, u32_to_f64bits};
+use std::fs::File;
+use std::io::{self, Write, BufWriter};
+use std::path::Path;
+
+/// A formula in conjunctive normal form that is satisfiable iff the encoded
+/// `Term`s can take the values fixed by the unit clauses.
+///
+/// Each `Symbol` and each `Xor`, `And` and `Or` gets a variable, with clauses
+/// forcing the gate variables to the value of the gate (Tseitin transformation).
+/// `Not` just negates the literal of its operand and constants share one
+/// variable that is fixed to true. Literals use the DIMACS convention: variables
+/// are numbered from 1 and a negative number is a negated variable.
+///
+/// The `inputs` and `outputs` registered by `sha()` or `add_input()` and
+/// `add_output()` make up the variable map, which relates the variables to
+/// the message bits and digest bits.
+#[derive(Clone, Default, Debug)]
+pub struct Cnf {
+    nr_of_vars : usize,
+    clauses : Vec<Vec<i32>>,
+    /// Literal of each encoded `Term`, indexed by `RTerm::index()`, 0 if not encoded.
+    lits : Vec<i32>,
+    /// The variable that is fixed to true, 0 if not needed yet.
+    true_var : i32,
+    /// Input bit position and `Symbol`.
+    inputs : Vec<(usize, RTerm)>,
+    outputs : Vec<RTerm>,
+}
+
+impl Cnf {
+
+    /// An empty formula.
+    pub fn new() -> Cnf {
+        Cnf::default()
+    }
+
+    /// Encodes the digest of `sha`. The digest bits are the outputs, in the
+    /// same order as returned by `Sha256::evaluate()`, and the `Symbol`s in
+    /// `sha.data` are the inputs, 0 being the MSBit of the first byte.
+    pub fn sha(sha : &Sha256) -> Cnf {
+        let mut cnf = Cnf::new();
+        for (w, u) in sha.data.iter().enumerate() {
+            for (j, b) in u.bits().iter().rev().enumerate() {
+                if let TermType::Symbol(_) = b.term_type() {
+                    cnf.add_input(w * 32 + j, *b);
+                }
+            }
+        }
+        for u in sha.digest.iter() {
+            for b in u.bits().iter().rev() {
+                cnf.add_output(*b);
+            }
+        }
+        cnf
+    }
+
+    /// Number of variables.
+    pub fn nr_of_vars(&self) -> usize {
+        self.nr_of_vars
+    }
+
+    /// The clauses.
+    pub fn clauses(&self) -> &[Vec<i32>] {
+        &self.clauses
+    }
+
+    /// The input bit positions and their `Symbol`s.
+    pub fn inputs(&self) -> &[(usize, RTerm)] {
+        &self.inputs
+    }
+
+    /// The output `Term`s.
+    pub fn outputs(&self) -> &[RTerm] {
+        &self.outputs
+    }
+
+    /// Encodes the `Symbol` `t` and registers it as input bit `position`.
+    pub fn add_input(&mut self, position : usize, t : RTerm) {
+        if let TermType::Symbol(_) = t.term_type() {
+            self.encode(&[t]);
+            self.inputs.push((position, t));
+        } else {
+            panic!("Input is not a symbol.");
+        }
+    }
+
+    /// Encodes `t` and registers it as the next output.
+    pub fn add_output(&mut self, t : RTerm) {
+        self.encode(&[t]);
+        self.outputs.push(t);
+    }
+
+    /// The literal of `t`, if it has been encoded.
+    pub fn lit(&self, t : RTerm) -> Option<i32> {
+        match self.lits.get(t.index()) {
+            Some(&l) if l != 0 => Some(l),
+            _ => None,
+        }
+    }
+
+    /// Adds a clause.
+    pub fn add_clause(&mut self, clause : &[i32]) {
+        assert!(clause.iter().all(|l| *l != 0 && l.unsigned_abs() as usize <= self.nr_of_vars));
+        self.clauses.push(clause.to_vec());
+    }
+
+    /// Encodes `t` (if needed) and adds a unit clause fixing it to `value`.
+    pub fn fix(&mut self, t : RTerm, value : bool) {
+        let l = self.encode(&[t])[0];
+        self.add_clause(&[if value { l } else { -l }]);
+    }
+
+    /// Fixes the outputs to the hash `target_hash` (64 hex characters).
+    ///
+    /// Panics if there are less than 256 outputs.
+    pub fn fix_digest(&mut self, target_hash : &str) {
+        let bits : Vec<bool> = u32_to_f64bits(dehex(target_hash).as_ref()).iter().map(|b| *b == 1.).collect();
+        self.fix_outputs(&bits);
+    }
+
+    /// Fixes the first `bits.len()` outputs.
+    pub fn fix_outputs(&mut self, bits : &[bool]) {
+        assert!(bits.len() <= self.outputs.len());
+        let outputs = self.outputs.clone();
+        for (t, b) in outputs.iter().zip(bits.iter()) {
+            self.fix(*t, *b);
+        }
+    }
+
+    /// Fixes input byte `bytenum` of the message to `value`. Input bits that
+    /// are not registered (e.g. because they are constant) are skipped.
+    pub fn fix_input_byte(&mut self, bytenum : usize, value : u8) {
+        let fixed : Vec<(RTerm, bool)> = self.inputs.iter()
+            .filter(|&&(pos, _)| pos / 8 == bytenum)
+            .map(|&(pos, t)| (t, value >> (7 - pos % 8) & 1 == 1))
+            .collect();
+        for (t, v) in fixed {
+            self.fix(t, v);
+        }
+    }
+
+    /// Encodes all `Term`s that `roots` depend on and returns the literals of the roots.
+    pub fn encode(&mut self, roots : &[RTerm]) -> Vec<i32> {
+        TermGraph::with(|g| {
+            let needed = g.cone(roots);
+            if self.lits.len() < needed.len() {
+                self.lits.resize(needed.len(), 0);
+            }
+            for (i, &n) in needed.iter().enumerate() {
+                if !n || self.lits[i] != 0 {
+                    continue;
+                }
+                self.lits[i] = match g.term_type(g.id(i)) {
+                    TermType::Symbol(_) => self.new_var(),
+                    TermType::Constant(c) => {
+                        if self.true_var == 0 {
+                            self.true_var = self.new_var();
+                            let t = self.true_var;
+                            self.clauses.push(vec![t]);
+                        }
+                        if c { self.true_var } else { -self.true_var }
+                    },
+                    TermType::Not(x) => -self.lits[x.index()],
+                    TermType::And(x, y) => {
+                        let (a, b, c) = (self.lits[x.index()], self.lits[y.index()], self.new_var());
+                        self.clauses.push(vec![-c, a]);
+                        self.clauses.push(vec![-c, b]);
+                        self.clauses.push(vec![c, -a, -b]);
+                        c
+                    },
+                    TermType::Or(x, y) => {
+                        let (a, b, c) = (self.lits[x.index()], self.lits[y.index()], self.new_var());
+                        self.clauses.push(vec![c, -a]);
+                        self.clauses.push(vec![c, -b]);
+                        self.clauses.push(vec![-c, a, b]);
+                        c
+                    },
+                    TermType::Xor(x, y) => {
+                        let (a, b, c) = (self.lits[x.index()], self.lits[y.index()], self.new_var());
+                        self.clauses.push(vec![-c, a, b]);
+                        self.clauses.push(vec![-c, -a, -b]);
+                        self.clauses.push(vec![c, -a, b]);
+                        self.clauses.push(vec![c, a, -b]);
+                        c
+                    },
+                };
+            }
+        });
+        roots.iter().map(|r| self.lits[r.index()]).collect()
+    }
+
+    fn new_var(&mut self) -> i32 {
+        assert!(self.nr_of_vars < i32::MAX as usize);
+        self.nr_of_vars += 1;
+        self.nr_of_vars as i32
+    }
+
+    /// Writes the formula in DIMACS format.
+    pub fn write_dimacs<W : Write>(&self, out : &mut W) -> io::Result<()> {
+        writeln!(out, "p cnf {} {}", self.nr_of_vars, self.clauses.len())?;
+        for c in self.clauses.iter() {
+            for l in c.iter() {
+                write!(out, "{} ", l)?;
+            }
+            writeln!(out, "0")?;
+        }
+        Ok(())
+    }
+
+    /// Writes one line `in <position> <literal>` for each input and one line
+    /// `out <index> <literal>` for each output.
+    pub fn write_var_map<W : Write>(&self, out : &mut W) -> io::Result<()> {
+        for &(pos, t) in self.inputs.iter() {
+            writeln!(out, "in {} {}", pos, self.lits[t.index()])?;
+        }
+        for (i, t) in self.outputs.iter().enumerate() {
+            writeln!(out, "out {} {}", i, self.lits[t.index()])?;
+        }
+        Ok(())
+    }
+
+    /// Writes the formula to `path` and the variable map to `path` with `.map` appended.
+    pub fn save(&self, path : &Path) -> io::Result<()> {
+        let mut cnf = BufWriter::new(File::create(path)?);
+        self.write_dimacs(&mut cnf)?;
+        let mut map_path = path.as_os_str().to_owned();
+        map_path.push(".map");
+        let mut map = BufWriter::new(File::create(map_path)?);
+        self.write_var_map(&mut map)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::u::U;
+    use ::term::Term;
+    use ::semantics::Standard;
+
+    /// Checks all clauses with the variables set to the boolean values of the `Term`s.
+    fn satisfied_by_evaluation(cnf : &Cnf) -> bool {
+        let mut vars = vec![None; cnf.nr_of_vars() + 1];
+        let encoded : Vec<RTerm> = TermGraph::with(|g| {
+            cnf.lits.iter().enumerate().filter(|&(_, l)| *l != 0).map(|(i, _)| g.id(i)).collect()
+        });
+        let frozen = TermGraph::with(|g| g.freeze(&encoded));
+        let values = frozen.evaluate(&frozen.symbols(), &Standard);
+        for (t, v) in encoded.iter().zip(values.iter()) {
+            let l = cnf.lits[t.index()];
+            let v = *v == 1.;
+            vars[l.unsigned_abs() as usize] = Some(if l > 0 { v } else { !v });
+        }
+        cnf.clauses().iter().all(|c| {
+            c.iter().any(|l| vars[l.unsigned_abs() as usize].unwrap() == (*l > 0))
+        })
+    }
+
+    #[test]
+    fn tseitin_gates() {
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let c = Term::symbol();
+        let x = Term::or(&Term::xor(&a, &b), &Term::not(&Term::and(&b, &c)));
+        let mut cnf = Cnf::new();
+        cnf.add_output(x);
+        assert_eq!(cnf.nr_of_vars(), 6);
+        assert_eq!(cnf.clauses().len(), 10);
+        for i in 0..8 {
+            a.set((i & 1) as f64);
+            b.set((i >> 1 & 1) as f64);
+            c.set((i >> 2 & 1) as f64);
+            assert!(satisfied_by_evaluation(&cnf));
+        }
+        cnf.fix(x, false);
+        a.set(1.);
+        b.set(1.);
+        c.set(1.);
+        assert!(satisfied_by_evaluation(&cnf));
+        a.set(0.);
+        assert!(!satisfied_by_evaluation(&cnf));
+    }
+
+    #[test]
+    fn sha_with_digest() {
+        let data = vec![U::new_symbolic()];
+        data[0].set_byte(b'a', 0);
+        data[0].set_byte(b'\n', 1);
+        let s = Sha256::new(data, 16);
+        let mut cnf = Cnf::sha(&s);
+        assert_eq!(cnf.inputs().len(), 16);
+        assert_eq!(cnf.outputs().len(), 256);
+        cnf.fix_digest("87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7");
+        cnf.fix_input_byte(0, b'a');
+        assert!(satisfied_by_evaluation(&cnf));
+        cnf.fix_input_byte(1, b'b');
+        assert!(!satisfied_by_evaluation(&cnf));
+
+        let mut dimacs = Vec::new();
+        cnf.write_dimacs(&mut dimacs).unwrap();
+        let dimacs = String::from_utf8(dimacs).unwrap();
+        assert!(dimacs.starts_with(&format!("p cnf {} {}\n", cnf.nr_of_vars(), cnf.clauses().len())));
+        assert_eq!(dimacs.lines().count(), cnf.clauses().len() + 1);
+
+        let mut map = Vec::new();
+        cnf.write_var_map(&mut map).unwrap();
+        let map = String::from_utf8(map).unwrap();
+        assert_eq!(map.lines().count(), 16 + 256);
+        assert!(map.starts_with("in 0 "));
+        assert!(map.contains("\nout 255 "));
+
+        let dir = ::std::env::temp_dir().join(format!("mostinefficientsha_cnf_{}", ::std::process::id()));
+        ::std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sha.cnf");
+        cnf.save(&path).unwrap();
+        assert_eq!(::std::fs::read_to_string(&path).unwrap(), dimacs);
+        assert_eq!(::std::fs::read_to_string(dir.join("sha.cnf.map")).unwrap(), map);
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+//! `dependency`: Which inputs each `Term` depends on (cone of influence).
+
+use ::term::{RTerm, TermId, TermType, TermGraph};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A fixed size set of indices, one bit per index.
+#[derive(Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Bitmap {
+    words : Vec<u64>,
+    len : usize,
+}
+
+/// Dependencies of all outputs on all inputs, e.g. of the 256 digest bits on
+/// the message bits. Row `k` is the set of inputs output `k` depends on.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DependencyMatrix {
+    rows : Vec<Bitmap>,
+    nr_of_inputs : usize,
+}
+
+impl Bitmap {
+    /// `len` bits, all cleared.
+    pub fn new(len : usize) -> Bitmap {
+        Bitmap { words : vec![0; len.div_ceil(64)], len }
+    }
+
+    /// Number of bits.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// True if there are no bits at all.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bit `i`. Panics if `i >= len()`.
+    pub fn get(&self, i : usize) -> bool {
+        assert!(i < self.len);
+        self.words[i / 64] >> (i % 64) & 1 == 1
+    }
+
+    /// Sets bit `i`. Panics if `i >= len()`.
+    pub fn set(&mut self, i : usize) {
+        assert!(i < self.len);
+        self.words[i / 64] |= 1 << (i % 64);
+    }
+
+    /// Sets all bits that are set in `other`, which must have the same length.
+    pub fn union_with(&mut self, other : &Bitmap) {
+        assert_eq!(self.len, other.len);
+        for (a, b) in self.words.iter_mut().zip(other.words.iter()) {
+            *a |= b;
+        }
+    }
+
+    /// Number of set bits.
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// The indices of the set bits, ascending.
+    pub fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(move |i| self.get(*i))
+    }
+}
+
+impl fmt::Display for Bitmap {
+    /// Writes e.g. `0110`, bit 0 first.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in 0..self.len {
+            write!(f, "{}", if self.get(i) { '1' } else { '0' })?;
+        }
+        Ok(())
+    }
+}
+
+impl DependencyMatrix {
+    /// The matrix with one row of length `nr_of_inputs` per output.
+    pub fn new(rows : Vec<Bitmap>, nr_of_inputs : usize) -> DependencyMatrix {
+        assert!(rows.iter().all(|r| r.len() == nr_of_inputs));
+        DependencyMatrix { rows, nr_of_inputs }
+    }
+
+    /// Number of outputs, i.e. rows.
+    pub fn nr_of_outputs(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Number of inputs, i.e. the length of each row.
+    pub fn nr_of_inputs(&self) -> usize {
+        self.nr_of_inputs
+    }
+
+    /// True if output `k` depends on input `j`.
+    pub fn depends(&self, k : usize, j : usize) -> bool {
+        self.rows[k].get(j)
+    }
+
+    /// The inputs output `k` depends on.
+    pub fn row(&self, k : usize) -> &Bitmap {
+        &self.rows[k]
+    }
+
+    /// The outputs input `j` reaches.
+    pub fn column(&self, j : usize) -> Bitmap {
+        let mut c = Bitmap::new(self.rows.len());
+        for (k, r) in self.rows.iter().enumerate() {
+            if r.get(j) {
+                c.set(k);
+            }
+        }
+        c
+    }
+
+    /// Fraction of all (output, input) pairs that depend on each other.
+    /// Full diffusion is 1.
+    pub fn density(&self) -> f64 {
+        let ones : usize = self.rows.iter().map(|r| r.count_ones()).sum();
+        ones as f64 / (self.rows.len() * self.nr_of_inputs).max(1) as f64
+    }
+}
+
+impl fmt::Display for DependencyMatrix {
+    /// Writes a summary line followed by one line per output, `1` where it
+    /// depends on an input.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let row_weights = self.rows.iter().map(|r| r.count_ones());
+        let column_weights = (0..self.nr_of_inputs).map(|j| self.column(j).count_ones());
+        write!(f, "Outputs: {}, inputs: {}, density: {:.4}, inputs per output: {}..{}, outputs per input: {}..{}",
+            self.nr_of_outputs(),
+            self.nr_of_inputs,
+            self.density(),
+            row_weights.clone().min().unwrap_or(0),
+            row_weights.max().unwrap_or(0),
+            column_weights.clone().min().unwrap_or(0),
+            column_weights.max().unwrap_or(0))?;
+        for r in self.rows.iter() {
+            write!(f, "\n{}", r)?;
+        }
+        Ok(())
+    }
+}
+
+impl TermGraph {
+    /// For each root, the `inputs` (by index into `inputs`) it depends on,
+    /// i.e. that are in its cone. Inputs are usually `Symbol`s, but may be any
+    /// `Term`s; the cone is not followed below them.
+    ///
+    /// This is structural: Dependencies the rewrite rules have not removed
+    /// count even if the function does not actually depend on the input.
+    ///
+    /// The bitmap of a `Term` is dropped after its last user in the cone, so
+    /// only the bitmaps of the current frontier are kept.
+    pub fn dependencies(&self, roots : &[RTerm], inputs : &[RTerm]) -> Vec<Bitmap> {
+        let index : HashMap<RTerm, usize> = inputs.iter().enumerate().map(|(j, t)| (*t, j)).collect();
+        let needed = self.cone(roots);
+        let operands = |i : usize| -> Vec<RTerm> {
+            if index.contains_key(&self.id(i)) {
+                return Vec::new();
+            }
+            match self.term_type(self.id(i)) {
+                TermType::Xor(x, y) | TermType::And(x, y) | TermType::Or(x, y) => vec![x, y],
+                TermType::Not(x) => vec![x],
+                TermType::Symbol(_) | TermType::Constant(_) => Vec::new(),
+            }
+        };
+        // Number of uses left of each bitmap, the roots are used at the end.
+        let mut uses = vec![0usize; needed.len()];
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            for x in operands(i) {
+                uses[x.index()] += 1;
+            }
+        }
+        for r in roots {
+            uses[r.index()] += 1;
+        }
+        let mut deps : Vec<Option<Bitmap>> = vec![None; needed.len()];
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            let mut d = Bitmap::new(inputs.len());
+            if let Some(j) = index.get(&self.id(i)) {
+                d.set(*j);
+            }
+            let operands = operands(i);
+            for x in operands.iter() {
+                d.union_with(deps[x.index()].as_ref().unwrap());
+            }
+            for x in operands.iter() {
+                uses[x.index()] -= 1;
+                if uses[x.index()] == 0 {
+                    deps[x.index()] = None;
+                }
+            }
+            deps[i] = Some(d);
+        }
+        roots.iter().map(|r| deps[r.index()].clone().unwrap()).collect()
+    }
+
+    /// For each of the `inputs`, the `roots` (by index into `roots`) that
+    /// depend on it. The transpose of `dependencies()`.
+    pub fn reach(&self, roots : &[RTerm], inputs : &[RTerm]) -> Vec<Bitmap> {
+        let m = DependencyMatrix::new(self.dependencies(roots, inputs), inputs.len());
+        (0..inputs.len()).map(|j| m.column(j)).collect()
+    }
+}
+
+impl TermId {
+    /// The `inputs` (by index into `inputs`) this `Term` depends on.
+    /// See `TermGraph::dependencies()`.
+    pub fn dependencies(&self, inputs : &[RTerm]) -> Bitmap {
+        TermGraph::with(|g| g.dependencies(&[*self], inputs)).pop().unwrap()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::term::Term;
+    use ::u::U;
+    use ::sha::Sha256;
+
+    #[test]
+    fn gates() {
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let c = Term::symbol();
+        let x = Term::and(&a, &b);
+        let y = Term::not(&Term::xor(&x, &c));
+        let inputs = [a, b, c];
+        assert_eq!(x.dependencies(&inputs).to_string(), "110");
+        assert_eq!(y.dependencies(&inputs).to_string(), "111");
+        assert_eq!(Term::c1().dependencies(&inputs).to_string(), "000");
+        // Below an input, the cone is not followed.
+        assert_eq!(y.dependencies(&[x]).to_string(), "1");
+        let reach = TermGraph::with(|g| g.reach(&[x, y, c], &inputs));
+        let reach : Vec<String> = reach.iter().map(|r| r.to_string()).collect();
+        assert_eq!(reach, vec!["110", "110", "011"]);
+        // The same operand twice, and a root that is an operand of another root.
+        let z = Term::xor(&x, &x);
+        let deps = TermGraph::with(|g| g.dependencies(&[z, x, z], &inputs));
+        let deps : Vec<String> = deps.iter().map(|d| d.to_string()).collect();
+        assert_eq!(deps, vec!["110", "110", "110"]);
+
+        let u = U::new_symbolic();
+        let deps = u.rotate_right(3).dependencies(u.bits());
+        assert!(deps.iter().enumerate().all(|(i, d)| d.ones().collect::<Vec<_>>() == vec![(i + 3) % 32]));
+    }
+
+    #[test]
+    fn diffusion() {
+        let data = vec![U::new_symbolic(), U::new_symbolic()];
+        let matrix = |rounds : usize| Sha256::with_rounds(data.clone(), 16, rounds).dependency_matrix();
+        let (m1, m4, m8) = (matrix(1), matrix(4), matrix(8));
+        println!("{}", m4);
+        assert_eq!((m1.nr_of_outputs(), m1.nr_of_inputs()), (256, 48));
+        // The second word enters in the second round.
+        assert!((32..48).all(|j| m1.column(j).count_ones() == 0));
+        assert!((0..32).all(|j| m1.column(j).count_ones() > 0));
+        // In round 1, only the new a and e depend on the message.
+        assert!(m1.row(64).count_ones() == 0);
+        assert!(m1.density() < m4.density() && m4.density() < m8.density());
+        assert_eq!(m8.density(), 1.);
+
+        let s = Sha256::with_rounds(data, 16, 4);
+        for j in [0, 20, 40].iter() {
+            assert_eq!(s.reach(*j), m4.column(*j));
+        }
+        assert_eq!(&s.dependencies()[100], m4.row(100));
+    }
+}
@@ -0,0 +1,216 @@
+//! `dot::Dot`: Export `Term` graphs to the Graphviz DOT format.
+
+use ::term::{RTerm, TermType, TermGraph};
+use ::semantics::{FuzzySemantics, Standard};
+use ::u::U;
+use ::sha::Sha256;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Writes the `Term`s some roots depend on as a Graphviz digraph.
+///
+/// Each `Term` becomes one node, no matter how often it is used, and edges
+/// point from the operands to the gates using them. Gates are labeled by
+/// their type. Symbols are labeled with their input bit position if they are
+/// registered with `inputs()` (`sha()` does this), with their symbol number
+/// otherwise.
+#[derive(Clone, Default, Debug)]
+pub struct Dot {
+    roots : Vec<(String, RTerm)>,
+    /// Input bit position by symbol number.
+    inputs : HashMap<usize, usize>,
+    max_depth : Option<usize>,
+    values : bool,
+}
+
+impl Dot {
+
+    /// Creates an exporter without roots.
+    pub fn new() -> Dot {
+        Dot::default()
+    }
+
+    /// Adds a root, which is drawn as an output named `name`.
+    pub fn root(mut self, name : &str, t : RTerm) -> Dot {
+        self.roots.push((name.to_string(), t));
+        self
+    }
+
+    /// Adds the bits of `u` as roots named `name[0]` (LSBit) to `name[31]` (MSBit).
+    pub fn u(mut self, name : &str, u : &U) -> Dot {
+        for (i, b) in u.bits().iter().enumerate() {
+            self.roots.push((format!("{}[{}]", name, i), *b));
+        }
+        self
+    }
+
+    /// Adds the digest of `sha` as roots named `h0[31]` to `h7[0]` and registers
+    /// its input data with `inputs()`.
+    pub fn sha(self, sha : &Sha256) -> Dot {
+        let mut dot = self.inputs(&sha.data);
+        for (i, u) in sha.digest.iter().enumerate() {
+            dot = dot.u(&format!("h{}", i), u);
+        }
+        dot
+    }
+
+    /// Labels the `Symbol`s in `data` with their position in the message,
+    /// 0 being the MSBit of the first byte.
+    pub fn inputs(mut self, data : &[U]) -> Dot {
+        TermGraph::with(|g| {
+            for (w, u) in data.iter().enumerate() {
+                for (j, b) in u.bits().iter().rev().enumerate() {
+                    if let TermType::Symbol(s) = g.term_type(*b) {
+                        self.inputs.insert(s, w * 32 + j);
+                    }
+                }
+            }
+        });
+        self
+    }
+
+    /// Only draws `Term`s up to `depth` gates away from the nearest root.
+    /// Operands beyond are drawn as a single `...` node each.
+    pub fn max_depth(mut self, depth : usize) -> Dot {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Annotates each node with its value. Unset symbols are `NaN`.
+    pub fn values(mut self, values : bool) -> Dot {
+        self.values = values;
+        self
+    }
+
+    /// Writes the graph to `out`, with values (if enabled) from the `Standard` semantics.
+    pub fn write<W : Write>(&self, out : &mut W) -> io::Result<()> {
+        self.write_with(out, &Standard)
+    }
+
+    /// Same as `write()`, but values are evaluated with `sem`.
+    pub fn write_with<W : Write, S : FuzzySemantics + ?Sized>(&self, out : &mut W, sem : &S) -> io::Result<()> {
+        TermGraph::with(|g| self.write_graph(g, out, sem))
+    }
+
+    /// The graph as a `String`. See `write()`.
+    pub fn to_dot(&self) -> String {
+        let mut out = Vec::new();
+        self.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn write_graph<W : Write, S : FuzzySemantics + ?Sized>(&self, g : &TermGraph, out : &mut W, sem : &S) -> io::Result<()> {
+        let roots : Vec<RTerm> = self.roots.iter().map(|r| r.1).collect();
+        let len = roots.iter().max().map_or(0, |r| r.index() + 1);
+
+        // Distance from the nearest root. Operands have smaller ids, so one
+        // descending sweep sees all users of a `Term` before the `Term` itself.
+        let mut depth = vec![usize::MAX; len];
+        for r in roots.iter() {
+            depth[r.index()] = 0;
+        }
+        for i in (0..len).rev() {
+            if depth[i] == usize::MAX || self.max_depth.is_some_and(|m| depth[i] > m) {
+                continue;
+            }
+            for o in operands(g.term_type(g.id(i))) {
+                depth[o.index()] = depth[o.index()].min(depth[i] + 1);
+            }
+        }
+
+        let values = if self.values {
+            let frozen = g.freeze(&roots);
+            let mut buffer = Vec::new();
+            frozen.evaluate_in(&frozen.symbols(), &mut buffer, sem);
+            buffer
+        } else {
+            Vec::new()
+        };
+
+        writeln!(out, "digraph terms {{")?;
+        for (i, d) in depth.iter().enumerate().filter(|&(_, d)| *d != usize::MAX) {
+            let t = g.term_type(g.id(i));
+            let (label, shape) = match t {
+                TermType::Symbol(s) => match self.inputs.get(&s) {
+                    Some(pos) => (format!("in[{}]", pos), "box"),
+                    None => (format!("s{}", s), "box"),
+                },
+                TermType::Constant(c) => ((if c { "1" } else { "0" }).to_string(), "box"),
+                TermType::Xor(..) => ("XOR".to_string(), "ellipse"),
+                TermType::And(..) => ("AND".to_string(), "ellipse"),
+                TermType::Or(..) => ("OR".to_string(), "ellipse"),
+                TermType::Not(..) => ("NOT".to_string(), "ellipse"),
+            };
+            if self.max_depth.is_some_and(|m| *d > m) {
+                writeln!(out, "  n{} [label=\"...\", shape=none];", i)?;
+                continue;
+            }
+            if self.values {
+                writeln!(out, "  n{} [label=\"{}\\n{:.4}\", shape={}];", i, label, values[i], shape)?;
+            } else {
+                writeln!(out, "  n{} [label=\"{}\", shape={}];", i, label, shape)?;
+            }
+            for o in operands(t) {
+                writeln!(out, "  n{} -> n{};", o.index(), i)?;
+            }
+        }
+        for (k, &(ref name, r)) in self.roots.iter().enumerate() {
+            writeln!(out, "  out{} [label=\"{}\", shape=plaintext];", k, name)?;
+            writeln!(out, "  n{} -> out{};", r.index(), k)?;
+        }
+        writeln!(out, "}}")
+    }
+}
+
+fn operands(t : TermType) -> Vec<RTerm> {
+    match t {
+        TermType::Xor(x, y) | TermType::And(x, y) | TermType::Or(x, y) => vec![x, y],
+        TermType::Not(x) => vec![x],
+        TermType::Symbol(_) | TermType::Constant(_) => vec![],
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::term::Term;
+
+    #[test]
+    fn shared_nodes_once() {
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let x = Term::xor(&a, &b);
+        let y = Term::and(&x, &Term::not(&b));
+        let z = Term::or(&x, &a);
+        a.set(1.);
+        b.set(0.);
+        let dot = Dot::new().root("y", y).root("z", z).values(true).to_dot();
+        println!("{}", dot);
+        assert!(dot.starts_with("digraph terms {"));
+        let node = format!("  n{} [", x.index());
+        assert_eq!(dot.matches(&node).count(), 1);
+        assert_eq!(dot.matches("XOR").count(), 1);
+        assert!(dot.contains("label=\"OR\\n1.0000\""));
+        assert!(dot.contains("label=\"z\", shape=plaintext"));
+        assert_eq!(dot.matches(&format!("n{} -> n", x.index())).count(), 2);
+    }
+
+    #[test]
+    fn cone_of_one_digest_bit() {
+        let data = vec![U::new_symbolic()];
+        let s = Sha256::new(data, 8);
+        let bit = s.digest[0].bits()[31];
+        let full = Dot::new().inputs(&s.data).root("h0[31]", bit).to_dot();
+        let limited = Dot::new().inputs(&s.data).root("h0[31]", bit).max_depth(4).to_dot();
+        assert!(full.contains("label=\"in[0]\""));
+        assert!(full.contains("label=\"in[7]\""));
+        assert!(!full.contains("in[8]"));
+        assert!(!full.contains("..."));
+        assert!(limited.contains("..."));
+        assert!(limited.len() * 100 < full.len());
+
+        let all = Dot::new().sha(&s).max_depth(1).to_dot();
+        assert!(all.contains("\"h7[0]\""));
+    }
+}
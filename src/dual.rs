@@ -0,0 +1,152 @@
+//! `dual::Dual`: Forward mode differentiation with dual numbers.
+
+use ::term::{RTerm, TermId, TermType, TermGraph};
+use ::semantics::FuzzySemantics;
+
+/// A fuzzy bit `v` together with its directional derivative `d`.
+///
+/// Evaluating a `Term` graph with `Dual`s instead of `f64`s yields the value
+/// of each `Term` and, in the same pass, its derivative along a direction in
+/// the space of the `Symbol` values (Jacobian-vector product).
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Dual {
+    /// The value, same as `evaluate()` would return.
+    pub v : f64,
+    /// The derivative of `v` along the chosen direction.
+    pub d : f64,
+}
+
+impl Dual {
+    /// A value that does not change along the direction.
+    pub fn constant(v : f64) -> Dual {
+        Dual { v, d : 0. }
+    }
+
+    /// Fuzzy `self ^ b` by the chain rule.
+    pub fn xor<S : FuzzySemantics + ?Sized>(self, b : Dual, sem : &S) -> Dual {
+        let (da, db) = sem.d_xor(self.v, b.v);
+        Dual { v : sem.xor(self.v, b.v), d : da * self.d + db * b.d }
+    }
+
+    /// Fuzzy `self & b` by the chain rule.
+    pub fn and<S : FuzzySemantics + ?Sized>(self, b : Dual, sem : &S) -> Dual {
+        let (da, db) = sem.d_and(self.v, b.v);
+        Dual { v : sem.and(self.v, b.v), d : da * self.d + db * b.d }
+    }
+
+    /// Fuzzy `self | b` by the chain rule.
+    pub fn or<S : FuzzySemantics + ?Sized>(self, b : Dual, sem : &S) -> Dual {
+        let (da, db) = sem.d_or(self.v, b.v);
+        Dual { v : sem.or(self.v, b.v), d : da * self.d + db * b.d }
+    }
+
+    /// Fuzzy `!self` by the chain rule.
+    pub fn not<S : FuzzySemantics + ?Sized>(self, sem : &S) -> Dual {
+        Dual { v : sem.not(self.v), d : sem.d_not(self.v) * self.d }
+    }
+}
+
+impl TermGraph {
+
+    /// Forward mode evaluation of `roots` with dual numbers.
+    ///
+    /// `direction` lists the `Symbol` type `Term`s that change, together with
+    /// their rate of change. All other symbols stay constant. All symbols the
+    /// roots depend on must be set.
+    ///
+    /// This does not use or modify the eval cache, so no `reset()` is needed.
+    ///
+    /// Panics if a `Term` in `direction` is not of type `Symbol`.
+    pub fn evaluate_dual<S : FuzzySemantics + ?Sized>(&self, roots : &[RTerm], direction : &[(RTerm, f64)], sem : &S) -> Vec<Dual> {
+        let mut tangent = vec![0.; self.nr_of_symbols()];
+        for &(t, d) in direction {
+            match self.term_type(t) {
+                TermType::Symbol(s) => tangent[s] += d,
+                _ => panic!("Direction given for non-symbol"),
+            }
+        }
+        let needed = self.cone(roots);
+        let mut duals = vec![Dual::default(); needed.len()];
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            duals[i] = match self.term_type(self.id(i)) {
+                TermType::Symbol(s) => Dual {
+                    v : self.symbol_value(s).expect("Symbol not set. Eval failed."),
+                    d : tangent[s],
+                },
+                TermType::Constant(c) => Dual::constant(if c { 1. } else { 0. }),
+                TermType::Xor(x, y) => duals[x.index()].xor(duals[y.index()], sem),
+                TermType::And(x, y) => duals[x.index()].and(duals[y.index()], sem),
+                TermType::Or(x, y) => duals[x.index()].or(duals[y.index()], sem),
+                TermType::Not(x) => duals[x.index()].not(sem),
+            };
+        }
+        roots.iter().map(|r| duals[r.index()]).collect()
+    }
+}
+
+impl TermId {
+    /// Value and directional derivative of this term along `direction`.
+    /// See `TermGraph::evaluate_dual()`.
+    pub fn evaluate_dual_with<S : FuzzySemantics + ?Sized>(&self, direction : &[(RTerm, f64)], sem : &S) -> Dual {
+        TermGraph::with(|g| g.evaluate_dual(&[*self], direction, sem)[0])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::u::U;
+    use ::sha::Sha256;
+    use ::semantics::{Standard, Probabilistic};
+
+    #[test]
+    fn jvp_matches_gradient() {
+        let u1 = U::new_symbolic();
+        let u2 = U::new_symbolic();
+        for (i, b) in u1.bits().iter().chain(u2.bits().iter()).enumerate() {
+            b.set((i % 7) as f64 / 7. + 0.05);
+        }
+        let sum = &u1 + &u2.rotate_right(5);
+        let roots : Vec<RTerm> = sum.bits().to_vec();
+        let weights : Vec<f64> = (0..32).map(|i| (i % 5) as f64 - 2.).collect();
+        let direction : Vec<(RTerm, f64)> = u1.bits().iter().chain(u2.bits().iter())
+                                              .enumerate()
+                                              .map(|(i, b)| (*b, (i % 3) as f64 - 1.))
+                                              .collect();
+
+        sum.reset();
+        let (duals, grad) = TermGraph::with_mut(|g| {
+            (g.evaluate_dual(&roots, &direction, &Probabilistic),
+             g.gradient(&roots, &weights, &Probabilistic))
+        });
+
+        // weights . (J direction) == (weights J) . direction
+        let forward : f64 = duals.iter().zip(weights.iter()).map(|(d, w)| d.d * w).sum();
+        let backward : f64 = direction.iter().map(|&(t, d)| {
+            match t.term_type() { TermType::Symbol(s) => grad[s] * d, _ => unreachable!() }
+        }).sum();
+        assert!((forward - backward).abs() < 1e-9, "{} {}", forward, backward);
+
+        for (d, r) in duals.iter().zip(roots.iter()) {
+            assert_eq!(d.v, r.evaluate_with(&Probabilistic));
+        }
+    }
+
+    #[test]
+    fn byte_perturbation_spreads() {
+        let data = vec![U::new_symbolic()];
+        data[0].set_byte(b'a', 0);
+        data[0].set_byte(b'\n', 1);
+        let direction : Vec<(RTerm, f64)> = data[0].byte_bits(1).iter().map(|b| (*b, 1.)).collect();
+        let s = Sha256::new(data, 16);
+        let duals = s.evaluate_dual(&direction);
+        assert_eq!(duals.iter().map(|d| d.v).collect::<Vec<_>>(), s.evaluate());
+        let touched = duals.iter().filter(|d| d.d != 0.).count();
+        println!("{} of 256 digest bits change", touched);
+        assert!(touched > 0);
+
+        let none = s.evaluate_dual_with(&[], &Standard);
+        assert!(none.iter().all(|d| d.d == 0.));
+    }
+}
@@ -0,0 +1,174 @@
+//! `frozen::FrozenGraph`: Immutable snapshot of a `TermGraph` that can be shared between threads.
+
+use ::term::{RTerm, TermType, TermGraph};
+use ::semantics::FuzzySemantics;
+
+/// The `Term`s some roots depend on, copied out of the thread local `TermGraph`.
+///
+/// A `TermGraph` belongs to one thread, so `RTerm`s can not be evaluated on
+/// other threads. A `FrozenGraph` is plain data and `Send + Sync`, so it can be
+/// shared (e.g. by reference from scoped threads or in an `Arc`). It does not
+/// own any values: each evaluation gets the `Symbol` values and a value buffer,
+/// so each thread can evaluate a different input assignment concurrently.
+///
+/// `RTerm` indices and symbol numbers keep their meaning.
+#[derive(Clone, Debug)]
+pub struct FrozenGraph {
+    /// The `Term`s of the cone of `roots`, in topological order.
+    order : Vec<(usize, FrozenTerm)>,
+    /// `RTerm::index()` of each root.
+    roots : Vec<usize>,
+    /// Symbol values at the time of freezing, `NaN` if unset.
+    symbols : Vec<f64>,
+    /// Length of the value buffer.
+    len : usize,
+}
+
+/// A `TermType` with the `RTerm::index()` of the operands, which unlike
+/// `RTerm`s can be sent to other threads.
+#[derive(Clone, Copy, Debug)]
+enum FrozenTerm {
+    Symbol(usize),
+    Constant(bool),
+    Xor(usize, usize),
+    And(usize, usize),
+    Or(usize, usize),
+    Not(usize),
+}
+
+impl From<TermType> for FrozenTerm {
+    fn from(t : TermType) -> FrozenTerm {
+        match t {
+            TermType::Symbol(s) => FrozenTerm::Symbol(s),
+            TermType::Constant(c) => FrozenTerm::Constant(c),
+            TermType::Xor(x, y) => FrozenTerm::Xor(x.index(), y.index()),
+            TermType::And(x, y) => FrozenTerm::And(x.index(), y.index()),
+            TermType::Or(x, y) => FrozenTerm::Or(x.index(), y.index()),
+            TermType::Not(x) => FrozenTerm::Not(x.index()),
+        }
+    }
+}
+
+impl TermGraph {
+
+    /// Copies the `Term`s that `roots` depend on into a `FrozenGraph`.
+    pub fn freeze(&self, roots : &[RTerm]) -> FrozenGraph {
+        let needed = self.cone(roots);
+        let order = needed.iter()
+                          .enumerate()
+                          .filter(|&(_, n)| *n)
+                          .map(|(i, _)| (i, self.term_type(self.id(i)).into()))
+                          .collect();
+        FrozenGraph {
+            order,
+            roots : roots.iter().map(|r| r.index()).collect(),
+            symbols : (0..self.nr_of_symbols()).map(|s| self.symbol_value(s).unwrap_or(f64::NAN)).collect(),
+            len : needed.len(),
+        }
+    }
+}
+
+impl FrozenGraph {
+
+    /// `RTerm::index()` of the roots, in the order their values are returned
+    /// by `evaluate()`.
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    /// Number of `Term`s the roots depend on.
+    pub fn nr_of_terms(&self) -> usize {
+        self.order.len()
+    }
+
+    /// The values of all symbols at the time of freezing, indexed by the
+    /// number of the symbol. Unset symbols are `NaN`. Use this as a starting
+    /// point for the `symbols` argument of `evaluate()`.
+    pub fn symbols(&self) -> Vec<f64> {
+        self.symbols.clone()
+    }
+
+    /// Evaluates the roots with the given `Symbol` values (indexed by the number
+    /// of the symbol). `buffer` holds the values of all `Term`s afterwards and
+    /// can be reused for the next evaluation to avoid reallocation.
+    pub fn evaluate_in<S : FuzzySemantics + ?Sized>(&self, symbols : &[f64], buffer : &mut Vec<f64>, sem : &S) -> Vec<f64> {
+        buffer.resize(self.len, 0.);
+        for &(i, t) in self.order.iter() {
+            buffer[i] = match t {
+                FrozenTerm::Symbol(s) => symbols[s],
+                FrozenTerm::Constant(c) => if c { 1. } else { 0. },
+                FrozenTerm::Xor(x, y) => sem.xor(buffer[x], buffer[y]),
+                FrozenTerm::And(x, y) => sem.and(buffer[x], buffer[y]),
+                FrozenTerm::Or(x, y) => sem.or(buffer[x], buffer[y]),
+                FrozenTerm::Not(x) => sem.not(buffer[x]),
+            };
+        }
+        self.roots.iter().map(|r| buffer[*r]).collect()
+    }
+
+    /// Same as `evaluate_in()`, with a new buffer.
+    pub fn evaluate<S : FuzzySemantics + ?Sized>(&self, symbols : &[f64], sem : &S) -> Vec<f64> {
+        self.evaluate_in(symbols, &mut Vec::new(), sem)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::u::U;
+    use ::sha::Sha256;
+    use ::semantics::Standard;
+    use ::util::{hex, f64bits_to_u32};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn is_send_sync<T : Send + Sync>() {}
+
+    #[test]
+    fn threads_evaluate_concurrently() {
+        is_send_sync::<FrozenGraph>();
+        let data = vec![U::new_symbolic()];
+        data[0].set_byte(0, 0);
+        data[0].set_byte(0, 1);
+        let inputs : Vec<usize> = data[0].byte_bits(0).iter().chain(data[0].byte_bits(1).iter()).map(|b| {
+            match b.term_type() { TermType::Symbol(s) => s, _ => unreachable!() }
+        }).collect();
+        let s = Sha256::new(data, 16);
+        let frozen = Arc::new(s.freeze());
+        assert_eq!(frozen.roots().len(), 256);
+
+        let messages : [&[u8; 2]; 3] = [b"a\n", b"ab", b"zz"];
+        let handles : Vec<_> = messages.iter().map(|m| {
+            let (frozen, inputs, m) = (frozen.clone(), inputs.clone(), **m);
+            thread::spawn(move || {
+                let mut symbols = frozen.symbols();
+                for (i, s) in inputs.iter().enumerate() {
+                    symbols[*s] = (m[i / 8] >> (i % 8) & 1) as f64;
+                }
+                hex(&f64bits_to_u32(&frozen.evaluate(&symbols, &Standard)))
+            })
+        }).collect();
+        let hashes : Vec<String> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        // echo -n 'a\n', 'ab', 'zz' | sha256sum
+        assert_eq!(hashes, ["87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7",
+                            "fb8e20fc2e4c3f248c60c39bd652f3c1347298bb977b8b4d5903b85055620603",
+                            "4a60bf7d4bc1e485744cf7e8d0860524752fca1ce42331be7c439fd23043f151"]);
+    }
+
+    #[test]
+    fn matches_thread_local_evaluation() {
+        let u1 = U::new_symbolic();
+        let u2 = U::new_symbolic();
+        for (i, b) in u1.bits().iter().chain(u2.bits().iter()).enumerate() {
+            b.set((i % 5) as f64 / 4.);
+        }
+        let sum = &u1 + &u2.rotate_right(3);
+        let frozen = sum.freeze();
+        let mut out = vec![];
+        sum.evaluate(&mut out);
+        let mut buffer = vec![];
+        assert_eq!(frozen.evaluate_in(&frozen.symbols(), &mut buffer, &Standard), out);
+        assert!(frozen.nr_of_terms() <= buffer.len());
+    }
+}
@@ -0,0 +1,227 @@
+//! `interval::Interval`: Bounds of fuzzy bits for partially known inputs.
+
+use ::term::{RTerm, TermType, TermGraph};
+use ::semantics::FuzzySemantics;
+use ::u::U;
+
+/// A closed interval `[lo, hi]` within [0, 1] that a fuzzy bit is known to lie in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Interval {
+    pub lo : f64,
+    pub hi : f64,
+}
+
+/// Intervals of the `Symbol`s, for `TermGraph::evaluate_intervals()`.
+///
+/// Symbols without an interval use the value they have been `set()` to, or
+/// the whole range [0, 1] if they are unset. So a partially known input is
+/// described by setting the known bits and leaving the others unset.
+#[derive(Clone, Default)]
+pub struct Intervals {
+    /// Indexed by the number of the symbol.
+    intervals : Vec<Option<Interval>>,
+}
+
+impl Interval {
+    /// `[lo, hi]`. Panics unless `0 <= lo <= hi <= 1`.
+    pub fn new(lo : f64, hi : f64) -> Interval {
+        assert!(0. <= lo && lo <= hi && hi <= 1., "Invalid interval [{}, {}].", lo, hi);
+        Interval { lo, hi }
+    }
+
+    /// The interval containing only `x`.
+    pub fn point(x : f64) -> Interval {
+        Interval::new(x, x)
+    }
+
+    /// [0, 1], nothing is known.
+    pub fn unknown() -> Interval {
+        Interval::new(0., 1.)
+    }
+
+    /// `hi - lo`.
+    pub fn width(&self) -> f64 {
+        self.hi - self.lo
+    }
+
+    /// True if `x` lies in the interval.
+    pub fn contains(&self, x : f64) -> bool {
+        self.lo <= x && x <= self.hi
+    }
+
+    /// The rounded value of every fuzzy bit in the interval, if it is the
+    /// same for all of them: `true` if `lo >= 0.5`, `false` if `hi < 0.5`.
+    pub fn determined(&self) -> Option<bool> {
+        if self.lo >= 0.5 {
+            Some(true)
+        } else if self.hi < 0.5 {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// Range of `f` on the box `a` x `b`, found by evaluating `f` at the corners
+/// and at the points where the box meets the lines `x = 0.5`, `y = 0.5`,
+/// `x = y` and `x = 1 - y`. This is exact for functions that are monotone in
+/// each operand or piecewise linear with kinks only on these lines, which
+/// covers the operations of all semantics in this crate.
+pub fn range_on_box<F : Fn(f64, f64) -> f64>(f : F, a : Interval, b : Interval) -> Interval {
+    let mut candidates = Vec::with_capacity(32);
+    let mut push = |x : f64, y : f64| if a.contains(x) && b.contains(y) {
+        candidates.push((x, y));
+    };
+    for &x in [a.lo, a.hi, 0.5].iter() {
+        for &y in [b.lo, b.hi, 0.5, x, 1. - x].iter() {
+            push(x, y);
+        }
+    }
+    for &y in [b.lo, b.hi, 0.5].iter() {
+        for &x in [y, 1. - y].iter() {
+            push(x, y);
+        }
+    }
+    let values = candidates.iter().map(|&(x, y)| f(x, y).clamp(0., 1.));
+    let (lo, hi) = values.fold((1f64, 0f64), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    Interval::new(lo, hi)
+}
+
+impl Intervals {
+    /// No intervals, all symbols use their values.
+    pub fn new() -> Intervals {
+        Intervals::default()
+    }
+
+    /// The interval of the symbol with number `symbol`, if it has been set.
+    pub fn symbol_interval(&self, symbol : usize) -> Option<Interval> {
+        self.intervals.get(symbol).and_then(|i| *i)
+    }
+
+    /// Sets the interval of the `Symbol` type term `t`.
+    ///
+    /// Panics if the type is not `Symbol`.
+    pub fn set(&mut self, t : RTerm, interval : Interval) {
+        let s = match t.term_type() {
+            TermType::Symbol(s) => s,
+            _ => panic!("Called set on non-symbol"),
+        };
+        if self.intervals.len() <= s {
+            self.intervals.resize(s + 1, None);
+        }
+        self.intervals[s] = Some(interval);
+    }
+
+    /// Sets all bits of `u` to `interval`.
+    pub fn set_u(&mut self, u : &U, interval : Interval) {
+        for b in u.bits().iter() {
+            self.set(*b, interval);
+        }
+    }
+}
+
+impl TermGraph {
+    /// Bounds each root by propagating the intervals of the symbols through
+    /// the operations of `sem`. Every evaluation with symbol values within
+    /// their intervals gives values within the returned intervals.
+    ///
+    /// This does not use or modify the eval cache.
+    pub fn evaluate_intervals<S : FuzzySemantics + ?Sized>(&self, roots : &[RTerm], intervals : &Intervals, sem : &S) -> Vec<Interval> {
+        let needed = self.cone(roots);
+        let mut values = vec![Interval::unknown(); needed.len()];
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            let v = |x : RTerm| values[x.index()];
+            values[i] = match self.term_type(self.id(i)) {
+                TermType::Symbol(s) => intervals.symbol_interval(s).unwrap_or_else(|| {
+                    self.symbol_value(s).map_or(Interval::unknown(), Interval::point)
+                }),
+                TermType::Constant(c) => Interval::point(c as u8 as f64),
+                TermType::Xor(x, y) => sem.xor_interval(v(x), v(y)),
+                TermType::And(x, y) => sem.and_interval(v(x), v(y)),
+                TermType::Or(x, y) => sem.or_interval(v(x), v(y)),
+                TermType::Not(x) => sem.not_interval(v(x)),
+            };
+        }
+        roots.iter().map(|r| values[r.index()]).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::term::Term;
+    use ::sha::Sha256;
+    use ::semantics::{Standard, Probabilistic, Lukasiewicz, Goedel};
+
+    type Operation<'a> = &'a dyn Fn(f64, f64) -> f64;
+
+    /// Checks the intervals of the operations against a fine grid.
+    fn encloses_grid<S : FuzzySemantics>(sem : &S) {
+        let boxes = [Interval::new(0.1, 0.3), Interval::new(0.2, 0.9), Interval::point(0.6), Interval::unknown()];
+        for a in boxes.iter() {
+            for b in boxes.iter() {
+                let ops : [(Operation, Interval); 3] = [
+                    (&|x, y| sem.xor(x, y), sem.xor_interval(*a, *b)),
+                    (&|x, y| sem.and(x, y), sem.and_interval(*a, *b)),
+                    (&|x, y| sem.or(x, y), sem.or_interval(*a, *b)),
+                ];
+                for (f, range) in ops.iter() {
+                    let (mut lo, mut hi) = (1f64, 0f64);
+                    for i in 0..=50 {
+                        for j in 0..=50 {
+                            let v = f(a.lo + a.width() * i as f64 / 50., b.lo + b.width() * j as f64 / 50.);
+                            assert!(range.lo - 1e-12 <= v && v <= range.hi + 1e-12);
+                            lo = lo.min(v);
+                            hi = hi.max(v);
+                        }
+                    }
+                    // Tight up to the grid.
+                    assert!(lo - range.lo < 0.05 && range.hi - hi < 0.05);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn semantics() {
+        encloses_grid(&Standard);
+        encloses_grid(&Probabilistic);
+        encloses_grid(&Lukasiewicz);
+        encloses_grid(&Goedel);
+    }
+
+    #[test]
+    fn determined_digest_bits() {
+        let data = vec![U::new_symbolic()];
+        data[0].set_byte(b'a', 0);
+        let s = Sha256::with_rounds(data, 16, 2);
+        // The second byte is unknown.
+        let intervals = Intervals::new();
+        let bounds = s.evaluate_intervals(&intervals);
+        let determined = s.determined_bits(&intervals);
+        let nr_determined = determined.iter().filter(|d| d.is_some()).count();
+        assert!(nr_determined > 0 && nr_determined < 256);
+        // The bounds hold for every completion of the message.
+        for byte in [0u8, b'\n', 0x7f, 0xff].iter() {
+            s.data[0].set_byte(*byte, 1);
+            s.reset();
+            for ((v, b), d) in s.evaluate().iter().zip(bounds.iter()).zip(determined.iter()) {
+                assert!(b.contains(*v));
+                if let Some(d) = d {
+                    assert_eq!(*v == 1., *d);
+                }
+            }
+        }
+        // With everything known, all bits are determined.
+        assert!(s.determined_bits(&intervals).iter().all(|d| d.is_some()));
+
+        let mut intervals = Intervals::new();
+        let bits = s.data[0].byte_bits(1);
+        intervals.set(bits[0], Interval::new(0.4, 0.6));
+        intervals.set(bits[1], Interval::new(0.4, 0.6));
+        let t = Term::and(&bits[0], &bits[1]);
+        let b = TermGraph::with(|g| g.evaluate_intervals(&[t], &intervals, &Standard))[0];
+        assert!((b.lo - 0.16).abs() < 1e-12 && (b.hi - 0.36).abs() < 1e-12);
+    }
+}
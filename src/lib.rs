@@ -8,7 +8,9 @@
 //!
 //! [Term](term/struct.Term.html) represents a fuzzy bit either as a settable bit (constant or symbolic)
 //! or as a bitwise combination of other `Term`s, thereby creating a treelike graph of `Term`s.
-//! These terms are evaluated lazily.
+//! All `Term`s of a thread live in one [TermGraph](term/struct.TermGraph.html) arena and are
+//! addressed by copyable `RTerm` handles. These terms are evaluated lazily. How fuzzy bits
+//! are combined is defined by [FuzzySemantics](semantics/trait.FuzzySemantics.html).
 //!
 //! [U](u/struct.U.html) combines 32 `Term`s and represents a fuzzy integer.
 //! U also implements _high level_ operations like shifting, rotating, adding.
@@ -29,7 +31,26 @@ extern crate byteorder;
 extern crate test;
 
 pub mod util;
+pub mod semantics;
+pub mod dual;
+pub mod batch;
+pub mod bitslice;
+pub mod frozen;
 pub mod term;
+pub mod word;
 pub mod u;
 pub mod sha;
 pub mod linopt;
+pub mod dot;
+pub mod cnf;
+pub mod sat;
+pub mod aiger;
+pub mod smt;
+pub mod verilog;
+pub mod anf;
+pub mod bdd;
+pub mod probability;
+pub mod montecarlo;
+pub mod interval;
+pub mod ternary;
+pub mod dependency;
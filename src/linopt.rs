@@ -1,12 +1,15 @@
 //! `linopt::Linopt`: Linear optimization
 
 use ::u::U;
-use ::term::RTerm;
+use ::term::{RTerm, TermType, TermGraph};
 use ::sha::Sha256;
+use ::semantics::Standard;
+use ::interval::{Interval, Intervals};
 use ::util::{dehex, hex, f64bits_to_u32, u32_to_f64bits};
 use std::cmp::{min};
 use arrayvec::ArrayVec;
 use std::fmt;
+use std::thread;
 
 
 /// Simple optimizer that tries to use the fuzzy Sha256 implementation to
@@ -20,6 +23,16 @@ pub struct Linopt {
     input_bits : Vec<RTerm>,
 }
 
+/// Distance measure of `b` to `target_hash`. Return value between 0 and 1.
+fn distance(target_hash : &[f64], b : &[f64]) -> f64 {
+    assert!(256 == b.len());
+    let mut dist = 0.0;
+    for (a,b) in target_hash.iter().zip(b.iter()) {
+        dist += (a - b).abs();
+    }
+    dist / 256.0
+}
+
 impl Linopt {
 
     /// Uses `len_input_bytes` as the length of the input to the sha256 hash algorithm.
@@ -37,8 +50,8 @@ impl Linopt {
             let u = U::new_symbolic();
             // Keep references to all symbolic `Term`s.
             len_message_in_last_u_in_bits = min(len_input_bits, 32);
-            for b in u.bits.iter().rev().take(len_message_in_last_u_in_bits) {
-                input_bits.push(b.clone());
+            for b in u.bits().iter().rev().take(len_message_in_last_u_in_bits) {
+                input_bits.push(*b);
             }
             len_input_bits = len_input_bits.saturating_sub(32);
             input_data.push(u.clone());
@@ -46,20 +59,15 @@ impl Linopt {
 
         Linopt {
             sha : Sha256::new(input_data, len_message_in_last_u_in_bits),
-            target_hash : u32_to_f64bits(dehex(&target_hash).as_ref()),
-            input_bits : input_bits,
+            target_hash : u32_to_f64bits(dehex(target_hash).as_ref()),
+            input_bits,
         }
 
     }
 
     /// Distance measure to the `target_hash`. Return value between 0 and 1.
     fn distance(&self, b : &[f64]) -> f64 {
-        assert!(256 == b.len());
-        let mut dist = 0.0;
-        for (a,b) in self.target_hash.iter().zip(b.iter()) {
-            dist += (a - b).abs();
-        }
-        dist / 256.0
+        distance(&self.target_hash, b)
     }
 
     /// Inits all input bits to 0.5.
@@ -70,32 +78,136 @@ impl Linopt {
     }
 
     /// Run optimization for `rounds` rounds.
+    ///
+    /// Only one input bit changes at a time, so the hash is updated with
+    /// `Sha256::evaluate_changed()` instead of being evaluated from scratch.
     pub fn optimize(&self, rounds : usize) {
+        let mut unchanged = self.sha.evaluate();
         for _ in 0..rounds {
 
             for (i, b) in self.input_bits.iter().enumerate() {
 
                 // optimize input bit b
 
-                let unchanged = self.sha.evaluate();
                 let epsilon = 0.01;
                 let sign = if b.evaluate() <= epsilon { 1.0 } else {-1.0 };
                 let epsilon = epsilon * sign;
 
                 b.set(b.evaluate() + epsilon);
-                let changed = self.sha.evaluate();
+                let changed = self.sha.evaluate_changed(&[*b]);
                 let unchanged_dist = self.distance(&unchanged[..]);
                 println!("Dist: {}", unchanged_dist);
                 println!("{}", hex(&f64bits_to_u32(&changed[..])[..]));
                 let derivative = (self.distance(&changed[..]) - unchanged_dist) / epsilon;
 
-                b.set( (b.evaluate() + derivative * epsilon * 0.1).min(0.0).max(1.0) );
+                b.set( (b.evaluate() + derivative * epsilon * 0.1).clamp(0.0, 1.0) );
+                unchanged = self.sha.evaluate_changed(&[*b]);
                 println!("b[{}] = {}", i, b.evaluate());
 
             }
         }
     }
 
+    /// Parallel version of `optimize()` on `threads` worker threads.
+    ///
+    /// The digest `Term`s are frozen into a `FrozenGraph` once. In each round,
+    /// the input bits are split between the workers and each worker estimates
+    /// the derivatives of its bits with its own copy of the symbol values.
+    /// All bits are updated at once at the end of the round, so the result
+    /// differs from `optimize()`, which updates one bit after the other.
+    pub fn optimize_parallel(&self, rounds : usize, threads : usize) {
+        assert!(threads >= 1);
+        let symbol_nrs : Vec<usize> = self.input_bits.iter().map(|b| {
+            match b.term_type() {
+                TermType::Symbol(s) => s,
+                _ => panic!("Input bit is not a symbol."),
+            }
+        }).collect();
+        let frozen = self.sha.freeze();
+        let chunk_size = symbol_nrs.len().div_ceil(threads);
+        for _ in 0..rounds {
+            let mut symbols = frozen.symbols();
+            TermGraph::with(|g| for s in symbol_nrs.iter() {
+                symbols[*s] = g.symbol_value(*s).expect("Input bit not set.");
+            });
+            let unchanged_dist = self.distance(&frozen.evaluate(&symbols, &Standard));
+            println!("Dist: {}", unchanged_dist);
+            let new_values : Vec<f64> = thread::scope(|scope| {
+                let workers : Vec<_> = symbol_nrs.chunks(chunk_size).map(|chunk| {
+                    // `RTerm`s can not be used on other threads, so the workers
+                    // only get the target and the frozen graph.
+                    let (frozen, target_hash, mut symbols) = (&frozen, &self.target_hash, symbols.clone());
+                    scope.spawn(move || {
+                        let mut buffer = Vec::new();
+                        chunk.iter().map(|&s| {
+                            let value = symbols[s];
+                            let epsilon = 0.01;
+                            let sign = if value <= epsilon { 1.0 } else {-1.0 };
+                            let epsilon = epsilon * sign;
+                            symbols[s] = value + epsilon;
+                            let changed = frozen.evaluate_in(&symbols, &mut buffer, &Standard);
+                            symbols[s] = value;
+                            let derivative = (distance(target_hash, &changed[..]) - unchanged_dist) / epsilon;
+                            (value + derivative * epsilon * 0.1).clamp(0.0, 1.0)
+                        }).collect::<Vec<f64>>()
+                    })
+                }).collect();
+                workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+            });
+            for (b, v) in self.input_bits.iter().zip(new_values.iter()) {
+                b.set(*v);
+            }
+        }
+    }
+
+    /// Derivative of the distance to the `target_hash` with respect to each
+    /// input bit, calculated by one backward pass through the `Term`s instead
+    /// of one evaluation per input bit.
+    pub fn gradient(&self) -> Vec<f64> {
+        let current = self.sha.evaluate();
+        // d/db abs(t-b) / 256
+        let d_digest : Vec<f64> = self.target_hash.iter().zip(current.iter()).map(|(t, b)| {
+            if b > t { 1. / 256. } else if b < t { -1. / 256. } else { 0. }
+        }).collect();
+        let grad = self.sha.gradient(&d_digest);
+        self.input_bits.iter().map(|b| {
+            match b.term_type() {
+                TermType::Symbol(s) => grad[s],
+                _ => panic!("Input bit is not a symbol."),
+            }
+        }).collect()
+    }
+
+    /// Run gradient descent for `rounds` rounds. In each round, all input bits
+    /// move by `step` times their derivative at once.
+    pub fn optimize_gradient(&self, rounds : usize, step : f64) {
+        for _ in 0..rounds {
+            let grad = self.gradient();
+            for (b, d) in self.input_bits.iter().zip(grad.iter()) {
+                b.set( (b.evaluate() - step * d).clamp(0.0, 1.0) );
+            }
+            let changed = self.sha.evaluate();
+            println!("Dist: {}", self.distance(&changed[..]));
+            println!("{}", hex(&f64bits_to_u32(&changed[..])[..]));
+        }
+    }
+
+    /// True if no input with the bits `fixed` (index into the input bits, MSB
+    /// of the first byte first, and value) hashes to the `target_hash`, since
+    /// a digest bit that is determined by them differs from it. See
+    /// `Sha256::determined_bits()`. The other input bits may be anything.
+    pub fn is_pruned(&self, fixed : &[(usize, bool)]) -> bool {
+        let mut intervals = Intervals::new();
+        for b in self.input_bits.iter() {
+            intervals.set(*b, Interval::unknown());
+        }
+        for &(i, v) in fixed.iter() {
+            intervals.set(self.input_bits[i], Interval::point(v as u8 as f64));
+        }
+        self.sha.determined_bits(&intervals).iter().zip(self.target_hash.iter())
+            .any(|(d, t)| d.is_some_and(|d| d != (*t == 1.)))
+    }
+
     /// Evaluate sha algorithm to u32s.
     pub fn eval_to_u32(&self) -> ArrayVec<[u32; 8]> {
         self.sha.eval_to_u32()
@@ -126,4 +238,62 @@ mod tests {
         l.init();
     }
 
+    #[test]
+    fn optimize() {
+        let l = Linopt::new(1, "87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7");
+        l.init();
+        l.optimize(1);
+        let incremental = l.sha.evaluate_changed(&[]);
+        assert_eq!(incremental, l.sha.evaluate());
+        // Each bit moves by a small step from 0.5, it is not pushed to 1.
+        for b in l.input_bits.iter() {
+            let v = b.evaluate();
+            assert!((0.3..0.7).contains(&v), "{}", v);
+        }
+    }
+
+    #[test]
+    fn gradient() {
+        let l = Linopt::new(2, "87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7");
+        l.init();
+        let grad = l.gradient();
+        assert_eq!(grad.len(), 16);
+        assert!(grad.iter().any(|d| *d != 0.));
+        l.optimize_gradient(1, 0.1);
+        for b in l.input_bits.iter() {
+            let v = b.evaluate();
+            assert!((0. ..=1.).contains(&v));
+        }
+    }
+
+    #[test]
+    fn is_pruned() {
+        // sha256("a")
+        let l = Linopt::new(1, "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb");
+        let byte = |c : u8| -> Vec<(usize, bool)> { (0..8).map(|i| (i, c >> (7 - i) & 1 == 1)).collect() };
+        assert!(!l.is_pruned(&[]));
+        assert!(!l.is_pruned(&byte(b'a')));
+        assert!(l.is_pruned(&byte(b'b')));
+    }
+
+    #[test]
+    fn optimize_parallel() {
+        let l = Linopt::new(2, "87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7");
+        let start = |l : &Linopt| for (i, b) in l.input_bits.iter().enumerate() {
+            b.set((i % 4) as f64 / 4. + 0.1);
+        };
+        start(&l);
+        l.optimize_parallel(1, 3);
+        let values = |l : &Linopt| -> Vec<f64> {
+            l.input_bits.iter().map(|b| { b.reset(); b.evaluate() }).collect()
+        };
+        let parallel = values(&l);
+        assert!(parallel.iter().all(|v| (0. ..=1.).contains(v)));
+        assert!(parallel.iter().enumerate().any(|(i, v)| *v != (i % 4) as f64 / 4. + 0.1));
+        // Same result with a single worker.
+        start(&l);
+        l.optimize_parallel(1, 1);
+        assert_eq!(parallel, values(&l));
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,158 @@
+//! `montecarlo::MonteCarlo`: Sampling boolean inputs from fuzzy symbol values.
+
+use ::term::{RTerm, TermType, TermGraph};
+use ::bitslice::BitSlice;
+
+/// Per-root counts of ones over random boolean samples of the inputs.
+///
+/// Each `Symbol` value in [0, 1] is taken as the probability of the symbol
+/// being 1, independently of the others. Every sample draws all symbols and
+/// evaluates the roots with boolean logic, 64 samples per walk over the
+/// graph (see `BitSlice`). The fraction of ones estimates the true
+/// probability of each root, which the fuzzy semantics only approximate.
+#[derive(Clone, Debug)]
+pub struct MonteCarlo {
+    samples : usize,
+    ones : Vec<usize>,
+}
+
+/// SplitMix64, good enough for sampling and reproducible from a seed.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A uniform number in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl MonteCarlo {
+
+    /// Draws `samples` samples (rounded up to a multiple of 64) of the symbols
+    /// `roots` depend on and counts the ones of each root. The same `seed`
+    /// gives the same result.
+    ///
+    /// Panics if a symbol is unset.
+    pub fn sample(roots : &[RTerm], samples : usize, seed : u64) -> MonteCarlo {
+        // Symbols that are exactly 0 or 1 use their scalar value.
+        let fuzzy : Vec<(RTerm, f64)> = TermGraph::with(|g| {
+            let needed = g.cone(roots);
+            needed.iter().enumerate().filter(|&(_, n)| *n).filter_map(|(i, _)| match g.term_type(g.id(i)) {
+                TermType::Symbol(s) => {
+                    let v = g.symbol_value(s).expect("Symbol not set.");
+                    if v == 0. || v == 1. { None } else { Some((g.id(i), v)) }
+                },
+                _ => None,
+            }).collect()
+        });
+        let mut rng = SplitMix64(seed);
+        let mut mc = MonteCarlo { samples : 0, ones : vec![0; roots.len()] };
+        while mc.samples < samples {
+            let mut bits = BitSlice::new();
+            for &(t, p) in fuzzy.iter() {
+                let word = (0..BitSlice::LANES).fold(0u64, |w, lane| w | ((rng.next_f64() < p) as u64) << lane);
+                bits.set_word(t, word);
+            }
+            let words = TermGraph::with(|g| g.evaluate_bits(roots, &bits));
+            for (o, w) in mc.ones.iter_mut().zip(words.iter()) {
+                *o += w.count_ones() as usize;
+            }
+            mc.samples += BitSlice::LANES;
+        }
+        mc
+    }
+
+    /// Number of samples drawn.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Number of samples in which each root was 1.
+    pub fn ones(&self) -> &[usize] {
+        &self.ones
+    }
+
+    /// Empirical probability of each root being 1.
+    pub fn probabilities(&self) -> Vec<f64> {
+        self.ones.iter().map(|o| *o as f64 / self.samples.max(1) as f64).collect()
+    }
+
+    /// Wilson score interval `(low, high)` of the probability of each root,
+    /// for the standard normal quantile `z` (1.96 for 95% confidence).
+    /// Without samples, the interval is `(0, 1)`.
+    pub fn confidence_intervals(&self, z : f64) -> Vec<(f64, f64)> {
+        if self.samples == 0 {
+            return vec![(0., 1.); self.ones.len()];
+        }
+        let n = self.samples as f64;
+        self.probabilities().iter().map(|p| {
+            let denominator = 1. + z * z / n;
+            let center = (p + z * z / (2. * n)) / denominator;
+            let half = z * (p * (1. - p) / n + z * z / (4. * n * n)).sqrt() / denominator;
+            ((center - half).max(0.), (center + half).min(1.))
+        }).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::term::Term;
+    use ::u::U;
+    use ::sha::Sha256;
+    use ::bdd::Bdd;
+    use ::probability::Deviation;
+
+    #[test]
+    fn shared_operand() {
+        // (a ^ b) & a = a & !b, the operands of the And are not independent.
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let x = Term::and(&Term::xor(&a, &b), &a);
+        a.set(0.5);
+        b.set(0.25);
+        let mc = MonteCarlo::sample(&[x], 20_000, 1);
+        assert_eq!(mc.samples(), 20_032);
+        let exact = 0.5 * 0.75;
+        let (low, high) = mc.confidence_intervals(4.)[0];
+        assert!(low < exact && exact < high);
+        assert!(high - low < 0.05);
+        assert_eq!(mc.ones(), MonteCarlo::sample(&[x], 20_000, 1).ones());
+        assert_eq!(MonteCarlo::sample(&[x], 0, 1).confidence_intervals(4.), vec![(0., 1.)]);
+    }
+
+    #[test]
+    fn reduced_round_sha() {
+        let data = vec![U::new_symbolic()];
+        data[0].set_bytes(b"ab\0\0");
+        let s = Sha256::with_rounds(data, 16, 4);
+        let order = Bdd::message_order(&s.data);
+        for t in order[8..].iter() {
+            t.set(0.8);
+        }
+        let roots : Vec<RTerm> = s.digest.iter().flat_map(|u| u.bits().iter().rev().cloned()).collect();
+        let mut bdd = Bdd::new(&order[8..], 1 << 16);
+        let f = bdd.build(&roots).unwrap();
+        let p = bdd.symbol_probabilities();
+        let exact : Vec<f64> = f.iter().map(|f| bdd.probability(*f, &p)).collect();
+
+        let mc = s.monte_carlo(4096, 7);
+        let sampled = Deviation::between(&mc.probabilities(), &exact);
+        s.reset();
+        let fuzzy = Deviation::between(&s.evaluate(), &exact);
+        println!("Monte Carlo: {}\nStandard: {}", sampled, fuzzy);
+        assert!(sampled.mean < fuzzy.mean);
+        let inside = mc.confidence_intervals(4.).iter().zip(exact.iter())
+            .filter(|&(&(low, high), e)| low <= *e && *e <= high).count();
+        assert!(inside >= 254);
+    }
+}
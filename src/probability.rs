@@ -0,0 +1,210 @@
+//! `probability`: Exact signal probabilities of `Term`s with correlated operands.
+
+use ::term::{RTerm, TermType, TermGraph};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// Largest supported `max_leaves`. Each cut is enumerated completely.
+pub const MAX_LEAVES : usize = 16;
+
+/// How far one vector of probabilities is from another.
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Deviation {
+    /// Largest absolute difference.
+    pub max : f64,
+    /// Mean absolute difference.
+    pub mean : f64,
+}
+
+impl Deviation {
+    /// The deviation of `values` from `reference`.
+    pub fn between(values : &[f64], reference : &[f64]) -> Deviation {
+        assert_eq!(values.len(), reference.len());
+        let mut d = Deviation::default();
+        for (v, r) in values.iter().zip(reference.iter()) {
+            d.max = d.max.max((v - r).abs());
+            d.mean += (v - r).abs();
+        }
+        d.mean /= values.len().max(1) as f64;
+        d
+    }
+}
+
+impl fmt::Display for Deviation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "max: {:.6}, mean: {:.6}", self.max, self.mean)
+    }
+}
+
+impl TermGraph {
+    /// Probability of each root being 1 if every `Symbol` is an independent
+    /// random bit that is 1 with the probability it has been `set()` to.
+    ///
+    /// The fuzzy semantics (even `Probabilistic`) assume the operands of each
+    /// gate to be independent, which they are not if they share `Term`s, like
+    /// the carries in `U::add()`. Here the probability of each gate is computed
+    /// from a cut of its cone instead: Starting with its operands, gates of the
+    /// cut are replaced by their operands as long as the cut has at most
+    /// `max_leaves` `Term`s, preferring those that add the fewest leaves.
+    /// `Term`s that are always 0 or 1 are never leaves. The function of the
+    /// gate in the cut leaves is then enumerated completely, with the leaves
+    /// assumed to be independent. So correlations within the cut are exact,
+    /// and the result is exact if the cut reaches the symbols. The effort per
+    /// gate grows with `2^max_leaves`.
+    ///
+    /// Panics if a symbol is unset or `max_leaves > MAX_LEAVES`.
+    pub fn signal_probabilities(&self, roots : &[RTerm], max_leaves : usize) -> Vec<f64> {
+        assert!(max_leaves <= MAX_LEAVES);
+        let needed = self.cone(roots);
+        let mut p = vec![0.; needed.len()];
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            p[i] = match self.term_type(self.id(i)) {
+                TermType::Symbol(s) => self.symbol_value(s).expect("Symbol not set."),
+                TermType::Constant(c) => c as u8 as f64,
+                TermType::Not(x) => 1. - p[x.index()],
+                _ => self.cut_probability(i, &p, max_leaves),
+            };
+        }
+        roots.iter().map(|r| p[r.index()]).collect()
+    }
+
+    /// The operands of the `Term` with index `i` that are not always 0 or 1.
+    fn variable_operands(&self, i : usize, p : &[f64]) -> Vec<usize> {
+        let ops = match self.term_type(self.id(i)) {
+            TermType::Xor(x, y) | TermType::And(x, y) | TermType::Or(x, y) => vec![x, y],
+            TermType::Not(x) => vec![x],
+            TermType::Symbol(_) | TermType::Constant(_) => vec![],
+        };
+        ops.into_iter().map(|o| o.index()).filter(|o| p[*o] != 0. && p[*o] != 1.).collect()
+    }
+
+    /// Probability of gate `t` by enumerating a cut of at most `max_leaves`
+    /// leaves (or its operands), see `signal_probabilities()`.
+    fn cut_probability(&self, t : usize, p : &[f64], max_leaves : usize) -> f64 {
+        let mut leaves : BTreeSet<usize> = self.variable_operands(t, p).into_iter().collect();
+        let mut interior : BTreeSet<usize> = BTreeSet::new();
+        interior.insert(t);
+        loop {
+            let limit = max_leaves.max(leaves.len());
+            // The operands of `n` that would become new leaves.
+            let new_leaves = |n : usize| -> Vec<usize> {
+                self.variable_operands(n, p).into_iter().filter(|o| !leaves.contains(o) && !interior.contains(o)).collect()
+            };
+            // Prefer gates that add the fewest leaves, then the latest ones.
+            let next = leaves.iter().rev().cloned()
+                .filter(|&n| !matches!(self.term_type(self.id(n)), TermType::Symbol(_)))
+                .map(|n| (new_leaves(n).len(), n))
+                .filter(|&(new, _)| leaves.len() - 1 + new <= limit)
+                .min_by_key(|&(new, _)| new);
+            match next {
+                Some((_, n)) => {
+                    let new = new_leaves(n);
+                    leaves.remove(&n);
+                    leaves.extend(new);
+                    interior.insert(n);
+                },
+                None => break,
+            }
+        }
+
+        // Bit `a` of each word vector is the value for assignment `a` to the leaves.
+        let leaves : Vec<usize> = leaves.into_iter().collect();
+        let lanes = 1usize << leaves.len();
+        let nr_of_words = lanes.div_ceil(64);
+        let mut values : HashMap<usize, Vec<u64>> = HashMap::new();
+        for (k, l) in leaves.iter().enumerate() {
+            let word = (0..nr_of_words).map(|w| {
+                (0..64).fold(0u64, |v, lane| v | (((w * 64 + lane) >> k & 1) as u64) << lane)
+            }).collect();
+            values.insert(*l, word);
+        }
+        for n in interior.iter() {
+            // Operands that are always 0 or 1 are no leaves.
+            let get = |x : RTerm| match values.get(&x.index()) {
+                Some(v) => v.clone(),
+                None => vec![if p[x.index()] == 1. { !0u64 } else { 0 }; nr_of_words],
+            };
+            let v : Vec<u64> = match self.term_type(self.id(*n)) {
+                TermType::Xor(x, y) => get(x).iter().zip(get(y).iter()).map(|(a, b)| a ^ b).collect(),
+                TermType::And(x, y) => get(x).iter().zip(get(y).iter()).map(|(a, b)| a & b).collect(),
+                TermType::Or(x, y) => get(x).iter().zip(get(y).iter()).map(|(a, b)| a | b).collect(),
+                TermType::Not(x) => get(x).iter().map(|a| !a).collect(),
+                _ => unreachable!(),
+            };
+            values.insert(*n, v);
+        }
+
+        // Probability of each assignment, built up leaf by leaf.
+        let mut weights = vec![1.];
+        for l in leaves.iter() {
+            let pl = p[*l];
+            weights = weights.iter().map(|w| w * (1. - pl)).chain(weights.iter().map(|w| w * pl)).collect();
+        }
+        let v = &values[&t];
+        (0..lanes).filter(|a| v[a / 64] >> (a % 64) & 1 == 1).map(|a| weights[a]).sum()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::term::Term;
+    use ::u::U;
+    use ::sha::Sha256;
+    use ::bdd::Bdd;
+    use ::semantics::Probabilistic;
+
+    #[test]
+    fn reconvergent_fanout() {
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let c = Term::symbol();
+        // Majority, as in the carry of a full adder.
+        let maj = Term::or(&Term::or(&Term::and(&a, &b), &Term::and(&a, &c)), &Term::and(&b, &c));
+        let (pa, pb, pc) = (0.2, 0.6, 0.9);
+        a.set(pa);
+        b.set(pb);
+        c.set(pc);
+        let exact = pa * pb + pa * pc + pb * pc - 2. * pa * pb * pc;
+        let p = TermGraph::with(|g| g.signal_probabilities(&[maj], 4))[0];
+        assert!((p - exact).abs() < 1e-12);
+        // Without room to expand, the operands are taken as independent.
+        let p = TermGraph::with(|g| g.signal_probabilities(&[maj], 2))[0];
+        maj.reset();
+        assert!((p - maj.evaluate_with(&Probabilistic)).abs() < 1e-12);
+        assert!((p - exact).abs() > 0.01);
+    }
+
+    #[test]
+    fn reduced_round_sha() {
+        let data = vec![U::new_symbolic()];
+        data[0].set_bytes(b"ab\0\0");
+        let s = Sha256::with_rounds(data, 16, 2);
+        let order = Bdd::message_order(&s.data);
+        for t in order[8..].iter() {
+            t.set(0.3);
+        }
+        let mut bdd = Bdd::new(&order[8..], 1 << 16);
+        let roots : Vec<RTerm> = s.digest.iter().flat_map(|u| u.bits().iter().rev().cloned()).collect();
+        let f = bdd.build(&roots).unwrap();
+        let p = bdd.symbol_probabilities();
+        let exact : Vec<f64> = f.iter().map(|f| bdd.probability(*f, &p)).collect();
+
+        s.reset();
+        let fuzzy = Deviation::between(&s.evaluate(), &exact);
+        s.reset();
+        let independent = Deviation::between(&s.evaluate_with(&Probabilistic), &exact);
+        let cut8 = s.signal_probabilities(8);
+        let cut = Deviation::between(&cut8, &exact);
+        println!("Standard: {}\nProbabilistic: {}\nCuts: {}", fuzzy, independent, cut);
+        assert!(cut.mean < independent.mean);
+        assert!(independent.mean < fuzzy.mean);
+        // More effort, more exact digest bits.
+        let full = s.signal_probabilities(MAX_LEAVES);
+        assert!(Deviation::between(&full, &exact).mean < cut.mean);
+        let nr_of_exact = |p : &[f64]| p.iter().zip(exact.iter()).filter(|&(a, b)| (a - b).abs() < 1e-9).count();
+        assert!(nr_of_exact(&full) > 200);
+        assert!(nr_of_exact(&full) > nr_of_exact(&cut8));
+    }
+}
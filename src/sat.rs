@@ -0,0 +1,632 @@
+//! `sat::Solver`: A small CDCL SAT solver for the formulas of `cnf::Cnf`.
+
+use ::cnf::Cnf;
+use std::mem;
+
+/// Outcome of `Solver::solve()`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SatResult {
+    /// A satisfying assignment, indexed by the DIMACS variable. Entry 0 is unused.
+    Sat(Vec<bool>),
+    Unsat,
+    /// The conflict budget was exhausted.
+    Unknown,
+}
+
+/// Internal literal: `2 * var + negated`, with `var` counted from 0.
+type Lit = usize;
+
+fn neg(l : Lit) -> Lit {
+    l ^ 1
+}
+
+fn var(l : Lit) -> usize {
+    l >> 1
+}
+
+fn from_dimacs(l : i32) -> Lit {
+    (l.unsigned_abs() as usize - 1) * 2 + (l < 0) as usize
+}
+
+const UNASSIGNED : i8 = 0;
+
+/// Conflict driven clause learning solver.
+///
+/// Uses two watched literals per clause for unit propagation, first UIP
+/// conflict analysis, the VSIDS decision heuristic with phase saving, and
+/// restarts after a Luby sequence of conflicts. At each restart, clauses
+/// satisfied at decision level 0 are removed and half of the long learnt
+/// clauses are forgotten.
+pub struct Solver {
+    clauses : Vec<Clause>,
+    /// Clauses watching each literal, i.e. to visit when it becomes false.
+    watches : Vec<Vec<usize>>,
+    /// Per variable: 1 true, -1 false, 0 unassigned.
+    assigns : Vec<i8>,
+    level : Vec<usize>,
+    reason : Vec<Option<usize>>,
+    /// Last value of each variable, used for the next decision.
+    phase : Vec<bool>,
+    trail : Vec<Lit>,
+    /// Start of each decision level in `trail`.
+    trail_lim : Vec<usize>,
+    /// Next trail entry to propagate.
+    qhead : usize,
+    activity : Vec<f64>,
+    var_inc : f64,
+    order : VarHeap,
+    seen : Vec<bool>,
+    /// An empty clause has been added or derived.
+    unsat : bool,
+    conflicts : usize,
+    decisions : usize,
+}
+
+struct Clause {
+    lits : Vec<Lit>,
+    learnt : bool,
+}
+
+impl Solver {
+
+    /// A solver with `nr_of_vars` variables and no clauses.
+    pub fn new(nr_of_vars : usize) -> Solver {
+        let mut order = VarHeap::default();
+        let activity = vec![0.; nr_of_vars];
+        for v in 0..nr_of_vars {
+            order.insert(v, &activity);
+        }
+        Solver {
+            clauses : Vec::new(),
+            watches : vec![Vec::new(); 2 * nr_of_vars],
+            assigns : vec![UNASSIGNED; nr_of_vars],
+            level : vec![0; nr_of_vars],
+            reason : vec![None; nr_of_vars],
+            phase : vec![false; nr_of_vars],
+            trail : Vec::new(),
+            trail_lim : Vec::new(),
+            qhead : 0,
+            activity,
+            var_inc : 1.,
+            order,
+            seen : vec![false; nr_of_vars],
+            unsat : false,
+            conflicts : 0,
+            decisions : 0,
+        }
+    }
+
+    /// Number of conflicts so far.
+    pub fn conflicts(&self) -> usize {
+        self.conflicts
+    }
+
+    /// Number of decisions so far.
+    pub fn decisions(&self) -> usize {
+        self.decisions
+    }
+
+    /// Adds a clause of DIMACS literals. Must be called before `solve()`.
+    pub fn add_clause(&mut self, clause : &[i32]) {
+        assert!(self.trail_lim.is_empty());
+        let mut lits : Vec<Lit> = clause.iter().map(|l| from_dimacs(*l)).collect();
+        lits.sort_unstable();
+        lits.dedup();
+        if lits.windows(2).any(|w| w[1] == neg(w[0])) {
+            return; // tautology
+        }
+        match lits.len() {
+            0 => self.unsat = true,
+            1 => match self.value(lits[0]) {
+                UNASSIGNED => self.enqueue(lits[0], None),
+                v if v < 0 => self.unsat = true,
+                _ => {},
+            },
+            _ => {
+                self.attach(Clause { lits, learnt : false });
+            },
+        }
+    }
+
+    /// Searches for a satisfying assignment without limit.
+    pub fn solve(&mut self) -> SatResult {
+        self.solve_limited(None)
+    }
+
+    /// Searches for a satisfying assignment, but gives up with `Unknown`
+    /// after `max_conflicts` further conflicts.
+    pub fn solve_limited(&mut self, max_conflicts : Option<usize>) -> SatResult {
+        if self.unsat {
+            return SatResult::Unsat;
+        }
+        let limit = max_conflicts.map(|m| self.conflicts + m);
+        let mut restart = 1;
+        loop {
+            let budget = 100 * luby(restart);
+            restart += 1;
+            match self.search(budget, limit) {
+                Some(true) => {
+                    let mut model = vec![false; self.assigns.len() + 1];
+                    for (v, a) in self.assigns.iter().enumerate() {
+                        model[v + 1] = *a > 0;
+                    }
+                    self.cancel_until(0);
+                    return SatResult::Sat(model);
+                },
+                Some(false) => {
+                    self.unsat = true;
+                    return SatResult::Unsat;
+                },
+                None => {
+                    self.cancel_until(0);
+                    if limit.is_some_and(|l| self.conflicts >= l) {
+                        return SatResult::Unknown;
+                    }
+                    self.reduce();
+                },
+            }
+        }
+    }
+
+    /// Runs until a model is found (`Some(true)`), unsatisfiability is proven
+    /// (`Some(false)`) or `budget` conflicts happened (`None`).
+    fn search(&mut self, budget : usize, limit : Option<usize>) -> Option<bool> {
+        let mut conflicts = 0;
+        loop {
+            if let Some(confl) = self.propagate() {
+                self.conflicts += 1;
+                conflicts += 1;
+                if self.trail_lim.is_empty() {
+                    return Some(false);
+                }
+                let (learnt, backtrack_level) = self.analyze(confl);
+                self.cancel_until(backtrack_level);
+                if learnt.len() == 1 {
+                    self.enqueue(learnt[0], None);
+                } else {
+                    let first = learnt[0];
+                    let ci = self.attach(Clause { lits : learnt, learnt : true });
+                    self.enqueue(first, Some(ci));
+                }
+                self.var_inc /= 0.95;
+            } else {
+                if conflicts >= budget || limit.is_some_and(|l| self.conflicts >= l) {
+                    return None;
+                }
+                match self.pick_branch_var() {
+                    None => return Some(true),
+                    Some(v) => {
+                        self.decisions += 1;
+                        self.trail_lim.push(self.trail.len());
+                        let l = 2 * v + (!self.phase[v]) as usize;
+                        self.enqueue(l, None);
+                    },
+                }
+            }
+        }
+    }
+
+    fn value(&self, l : Lit) -> i8 {
+        let a = self.assigns[var(l)];
+        if l & 1 == 1 { -a } else { a }
+    }
+
+    fn enqueue(&mut self, l : Lit, reason : Option<usize>) {
+        let v = var(l);
+        self.assigns[v] = if l & 1 == 1 { -1 } else { 1 };
+        self.level[v] = self.trail_lim.len();
+        self.reason[v] = reason;
+        self.trail.push(l);
+    }
+
+    fn attach(&mut self, c : Clause) -> usize {
+        let ci = self.clauses.len();
+        self.watches[c.lits[0]].push(ci);
+        self.watches[c.lits[1]].push(ci);
+        self.clauses.push(c);
+        ci
+    }
+
+    /// Unit propagation. Returns the conflicting clause, if any.
+    fn propagate(&mut self) -> Option<usize> {
+        while self.qhead < self.trail.len() {
+            let false_lit = neg(self.trail[self.qhead]);
+            self.qhead += 1;
+            let ws = mem::take(&mut self.watches[false_lit]);
+            let mut kept = Vec::with_capacity(ws.len());
+            let mut conflict = None;
+            for (k, &ci) in ws.iter().enumerate() {
+                if conflict.is_some() {
+                    kept.extend_from_slice(&ws[k..]);
+                    break;
+                }
+                let lits = &mut self.clauses[ci].lits;
+                if lits[0] == false_lit {
+                    lits.swap(0, 1);
+                }
+                let first = lits[0];
+                let a = self.assigns[var(first)];
+                if (if first & 1 == 1 { -a } else { a }) > 0 {
+                    kept.push(ci);
+                    continue;
+                }
+                // Look for a new literal to watch.
+                let mut moved = false;
+                for j in 2..lits.len() {
+                    let a = self.assigns[var(lits[j])];
+                    if (if lits[j] & 1 == 1 { -a } else { a }) >= 0 {
+                        lits.swap(1, j);
+                        self.watches[lits[1]].push(ci);
+                        moved = true;
+                        break;
+                    }
+                }
+                if moved {
+                    continue;
+                }
+                kept.push(ci);
+                match self.value(first) {
+                    UNASSIGNED => self.enqueue(first, Some(ci)),
+                    _ => conflict = Some(ci),
+                }
+            }
+            self.watches[false_lit] = kept;
+            if conflict.is_some() {
+                self.qhead = self.trail.len();
+                return conflict;
+            }
+        }
+        None
+    }
+
+    /// First UIP conflict analysis. Returns the learnt clause, asserting
+    /// literal first, and the level to backtrack to.
+    fn analyze(&mut self, mut confl : usize) -> (Vec<Lit>, usize) {
+        let current = self.trail_lim.len();
+        let mut learnt = vec![0];
+        let mut open = 0;
+        let mut p : Option<Lit> = None;
+        let mut index = self.trail.len();
+        loop {
+            for k in 0..self.clauses[confl].lits.len() {
+                let q = self.clauses[confl].lits[k];
+                if Some(q) == p {
+                    continue;
+                }
+                let v = var(q);
+                if !self.seen[v] && self.level[v] > 0 {
+                    self.bump(v);
+                    self.seen[v] = true;
+                    if self.level[v] >= current {
+                        open += 1;
+                    } else {
+                        learnt.push(q);
+                    }
+                }
+            }
+            // Next literal of the current level on the trail to resolve on.
+            loop {
+                index -= 1;
+                if self.seen[var(self.trail[index])] {
+                    break;
+                }
+            }
+            let l = self.trail[index];
+            self.seen[var(l)] = false;
+            open -= 1;
+            p = Some(l);
+            if open == 0 {
+                break;
+            }
+            confl = self.reason[var(l)].expect("Implied literal without reason.");
+        }
+        learnt[0] = neg(p.unwrap());
+        for l in learnt.iter() {
+            self.seen[var(*l)] = false;
+        }
+        let mut backtrack_level = 0;
+        if learnt.len() > 1 {
+            let mut max_k = 1;
+            for k in 2..learnt.len() {
+                if self.level[var(learnt[k])] > self.level[var(learnt[max_k])] {
+                    max_k = k;
+                }
+            }
+            learnt.swap(1, max_k);
+            backtrack_level = self.level[var(learnt[1])];
+        }
+        (learnt, backtrack_level)
+    }
+
+    fn bump(&mut self, v : usize) {
+        self.activity[v] += self.var_inc;
+        if self.activity[v] > 1e100 {
+            for a in self.activity.iter_mut() {
+                *a *= 1e-100;
+            }
+            self.var_inc *= 1e-100;
+        }
+        self.order.increased(v, &self.activity);
+    }
+
+    fn cancel_until(&mut self, level : usize) {
+        if self.trail_lim.len() <= level {
+            return;
+        }
+        let start = self.trail_lim[level];
+        for k in (start..self.trail.len()).rev() {
+            let v = var(self.trail[k]);
+            self.phase[v] = self.assigns[v] > 0;
+            self.assigns[v] = UNASSIGNED;
+            self.reason[v] = None;
+            self.order.insert(v, &self.activity);
+        }
+        self.trail.truncate(start);
+        self.trail_lim.truncate(level);
+        self.qhead = start;
+    }
+
+    fn pick_branch_var(&mut self) -> Option<usize> {
+        while let Some(v) = self.order.pop_max(&self.activity) {
+            if self.assigns[v] == UNASSIGNED {
+                return Some(v);
+            }
+        }
+        None
+    }
+
+    /// Simplifies the clause database at decision level 0: removes satisfied
+    /// clauses and false literals, and forgets half of the learnt clauses
+    /// longer than 2 literals.
+    fn reduce(&mut self) {
+        debug_assert!(self.trail_lim.is_empty());
+        let mut learnt_lens : Vec<usize> = self.clauses.iter().filter(|c| c.learnt).map(|c| c.lits.len()).collect();
+        learnt_lens.sort_unstable();
+        let max_len = learnt_lens.get(learnt_lens.len() / 2).cloned().unwrap_or(0).max(2);
+
+        let clauses = mem::take(&mut self.clauses);
+        for w in self.watches.iter_mut() {
+            w.clear();
+        }
+        for v in 0..self.reason.len() {
+            self.reason[v] = None;
+        }
+        for mut c in clauses {
+            if c.learnt && c.lits.len() > max_len {
+                continue;
+            }
+            if c.lits.iter().any(|l| self.value(*l) > 0) {
+                continue;
+            }
+            c.lits.retain(|l| self.assigns[var(*l)] == UNASSIGNED);
+            // After propagation at level 0 no clause is unit or empty.
+            debug_assert!(c.lits.len() >= 2);
+            self.attach(c);
+        }
+    }
+}
+
+/// The Luby sequence 1 1 2 1 1 2 4 1 1 2 ..., starting at `i = 1`.
+fn luby(i : usize) -> usize {
+    let mut i = i;
+    loop {
+        let mut k = 1;
+        while (1 << k) - 1 < i {
+            k += 1;
+        }
+        if i == (1 << k) - 1 {
+            return 1 << (k - 1);
+        }
+        i -= (1 << (k - 1)) - 1;
+    }
+}
+
+/// Max heap of variables by activity, with the position of each variable so
+/// that increased activities can be restored in place.
+#[derive(Default)]
+struct VarHeap {
+    heap : Vec<usize>,
+    /// Position in `heap`, `usize::MAX` if not contained.
+    pos : Vec<usize>,
+}
+
+impl VarHeap {
+    fn insert(&mut self, v : usize, act : &[f64]) {
+        if self.pos.len() <= v {
+            self.pos.resize(v + 1, usize::MAX);
+        }
+        if self.pos[v] != usize::MAX {
+            return;
+        }
+        self.pos[v] = self.heap.len();
+        self.heap.push(v);
+        self.up(self.heap.len() - 1, act);
+    }
+
+    fn increased(&mut self, v : usize, act : &[f64]) {
+        if let Some(&p) = self.pos.get(v) {
+            if p != usize::MAX {
+                self.up(p, act);
+            }
+        }
+    }
+
+    fn pop_max(&mut self, act : &[f64]) -> Option<usize> {
+        if self.heap.is_empty() {
+            return None;
+        }
+        let top = self.heap.swap_remove(0);
+        self.pos[top] = usize::MAX;
+        if !self.heap.is_empty() {
+            self.pos[self.heap[0]] = 0;
+            self.down(0, act);
+        }
+        Some(top)
+    }
+
+    fn up(&mut self, mut i : usize, act : &[f64]) {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if act[self.heap[parent]] >= act[self.heap[i]] {
+                break;
+            }
+            self.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    fn down(&mut self, mut i : usize, act : &[f64]) {
+        loop {
+            let (l, r) = (2 * i + 1, 2 * i + 2);
+            let mut largest = i;
+            if l < self.heap.len() && act[self.heap[l]] > act[self.heap[largest]] {
+                largest = l;
+            }
+            if r < self.heap.len() && act[self.heap[r]] > act[self.heap[largest]] {
+                largest = r;
+            }
+            if largest == i {
+                break;
+            }
+            self.swap(i, largest);
+            i = largest;
+        }
+    }
+
+    fn swap(&mut self, i : usize, j : usize) {
+        self.heap.swap(i, j);
+        self.pos[self.heap[i]] = i;
+        self.pos[self.heap[j]] = j;
+    }
+}
+
+impl Cnf {
+
+    /// A `Solver` loaded with the clauses of this formula.
+    pub fn solver(&self) -> Solver {
+        let mut solver = Solver::new(self.nr_of_vars());
+        for c in self.clauses() {
+            solver.add_clause(c);
+        }
+        solver
+    }
+
+    /// Solves this formula with the built-in `Solver`.
+    pub fn solve(&self) -> SatResult {
+        self.solver().solve()
+    }
+
+    /// Sets the input `Symbol`s to their value (0.0 or 1.0) in `model`, so
+    /// that the symbolic `U`s hold the solution.
+    pub fn set_inputs(&self, model : &[bool]) {
+        for &(_, t) in self.inputs() {
+            let l = self.lit(t).unwrap();
+            let v = model[l.unsigned_abs() as usize] == (l > 0);
+            t.set(if v { 1. } else { 0. });
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::u::U;
+    use ::sha::Sha256;
+    use ::util::hex;
+
+    fn satisfies(clauses : &[Vec<i32>], model : &[bool]) -> bool {
+        clauses.iter().all(|c| c.iter().any(|l| model[l.unsigned_abs() as usize] == (*l > 0)))
+    }
+
+    #[test]
+    fn luby_sequence() {
+        let l : Vec<usize> = (1..16).map(luby).collect();
+        assert_eq!(l, [1, 1, 2, 1, 1, 2, 4, 1, 1, 2, 1, 1, 2, 4, 8]);
+    }
+
+    #[test]
+    fn pigeonhole_is_unsat() {
+        // 4 pigeons, 3 holes. Variable p * 3 + h + 1: pigeon p sits in hole h.
+        let mut clauses = vec![];
+        for p in 0..4 {
+            clauses.push((0..3).map(|h| p * 3 + h + 1).collect::<Vec<i32>>());
+        }
+        for h in 0..3 {
+            for p in 0..4 {
+                for q in p + 1..4 {
+                    clauses.push(vec![-(p * 3 + h + 1), -(q * 3 + h + 1)]);
+                }
+            }
+        }
+        let mut solver = Solver::new(12);
+        for c in clauses.iter() {
+            solver.add_clause(c);
+        }
+        assert_eq!(solver.solve(), SatResult::Unsat);
+        assert!(solver.conflicts() > 0);
+    }
+
+    #[test]
+    fn random_3sat() {
+        // Under-constrained random instances (ratio 3) from a simple LCG.
+        let mut seed = 12345u64;
+        let mut next = |n : u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % n
+        };
+        for _ in 0..20 {
+            let vars = 60;
+            let clauses : Vec<Vec<i32>> = (0..3 * vars).map(|_| {
+                (0..3).map(|_| {
+                    let v = next(vars) as i32 + 1;
+                    if next(2) == 0 { v } else { -v }
+                }).collect()
+            }).collect();
+            let mut solver = Solver::new(vars as usize);
+            for c in clauses.iter() {
+                solver.add_clause(c);
+            }
+            match solver.solve() {
+                SatResult::Sat(model) => assert!(satisfies(&clauses, &model)),
+                r => panic!("{:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn reduced_round_preimage() {
+        let rounds = 8;
+        // The target: reduced-round hash of "a"
+        let data = vec![U::new_symbolic()];
+        data[0].set_byte(b'a', 0);
+        let s = Sha256::with_rounds(data, 8, rounds);
+        let target = s.hex();
+
+        let mut cnf = Cnf::sha(&s);
+        cnf.fix_digest(&target);
+        for b in s.data[0].byte_bits(0) {
+            b.set(0.);
+        }
+        assert_ne!(s.hex(), target);
+        let model = match cnf.solve() {
+            SatResult::Sat(model) => model,
+            r => panic!("{:?}", r),
+        };
+        assert!(satisfies(cnf.clauses(), &model));
+        cnf.set_inputs(&model);
+        assert_eq!(s.hex(), target);
+        assert_eq!(hex(&s.eval_to_u32()), target);
+    }
+
+    #[test]
+    fn conflict_budget() {
+        let data = vec![U::new_symbolic()];
+        data[0].set_byte(b'a', 0);
+        data[0].set_byte(b'b', 1);
+        let s = Sha256::with_rounds(data, 16, 16);
+        let mut cnf = Cnf::sha(&s);
+        cnf.fix_digest(&s.hex());
+        assert_eq!(cnf.solver().solve_limited(Some(0)), SatResult::Unknown);
+    }
+}
@@ -0,0 +1,342 @@
+//! `semantics::FuzzySemantics`: How fuzzy bits are combined.
+
+use ::interval::{Interval, range_on_box};
+
+/// Defines how the logical operations of `Term`s combine fuzzy bits.
+///
+/// Each method gets the already evaluated operands, which are numbers between
+/// 0.0 and 1.0, and returns the fuzzy result. Any implementation must agree
+/// with boolean logic if the operands are exactly 0.0 or 1.0, otherwise
+/// a `Sha256` evaluated with it would not calculate SHA-256 anymore.
+///
+/// Pass an implementation to the `evaluate_with()` methods of `RTerm`, `U`
+/// and `Sha256`. The plain `evaluate()` methods use [`Standard`](struct.Standard.html).
+///
+/// The `d_*` methods return the partial derivatives `(d/da, d/db)` of the
+/// operations. They are used by the backward pass of `TermGraph::gradient()`.
+/// Their default implementations use finite differences, so a user defined
+/// semantics only needs to provide them if exact derivatives are wanted.
+/// At kinks (like `a == b` for `abs(a-b)`), any one-sided derivative may be returned.
+///
+/// The `*_interval` methods return the range of the operations for operands
+/// within intervals, for `TermGraph::evaluate_intervals()`. Their default
+/// implementations use `interval::range_on_box()`, which is exact for the
+/// semantics of this crate. Other semantics may need to provide them.
+pub trait FuzzySemantics {
+    /// Fuzzy `a ^ b`.
+    fn xor(&self, a : f64, b : f64) -> f64;
+    /// Fuzzy `a & b`.
+    fn and(&self, a : f64, b : f64) -> f64;
+    /// Fuzzy `a | b`.
+    fn or(&self, a : f64, b : f64) -> f64;
+    /// Fuzzy `!a`. Defaults to `1-a`.
+    fn not(&self, a : f64) -> f64 {
+        1. - a
+    }
+
+    /// Partial derivatives of `xor(a, b)`.
+    fn d_xor(&self, a : f64, b : f64) -> (f64, f64) {
+        finite_differences(|a, b| self.xor(a, b), a, b)
+    }
+    /// Partial derivatives of `and(a, b)`.
+    fn d_and(&self, a : f64, b : f64) -> (f64, f64) {
+        finite_differences(|a, b| self.and(a, b), a, b)
+    }
+    /// Partial derivatives of `or(a, b)`.
+    fn d_or(&self, a : f64, b : f64) -> (f64, f64) {
+        finite_differences(|a, b| self.or(a, b), a, b)
+    }
+    /// Derivative of `not(a)`. Defaults to `-1`.
+    fn d_not(&self, _a : f64) -> f64 {
+        -1.
+    }
+
+    /// Range of `xor(a, b)` for `a` and `b` within the intervals.
+    fn xor_interval(&self, a : Interval, b : Interval) -> Interval {
+        range_on_box(|a, b| self.xor(a, b), a, b)
+    }
+    /// Range of `and(a, b)` for `a` and `b` within the intervals.
+    fn and_interval(&self, a : Interval, b : Interval) -> Interval {
+        range_on_box(|a, b| self.and(a, b), a, b)
+    }
+    /// Range of `or(a, b)` for `a` and `b` within the intervals.
+    fn or_interval(&self, a : Interval, b : Interval) -> Interval {
+        range_on_box(|a, b| self.or(a, b), a, b)
+    }
+    /// Range of `not(a)` for `a` within the interval.
+    fn not_interval(&self, a : Interval) -> Interval {
+        let (x, y) = (self.not(a.lo), self.not(a.hi));
+        Interval::new(x.min(y), x.max(y))
+    }
+}
+
+/// Step width of `finite_differences()`.
+const H : f64 = 1e-6;
+
+/// Smallest value `sqrt()` is differentiated at, to avoid infinite derivatives at 0.
+const SQRT_EPS : f64 = 1e-12;
+
+/// Partial derivatives of `f` at `(a, b)` by central differences. The
+/// differences are one-sided at the borders of `[0, 1]`.
+pub fn finite_differences<F : Fn(f64, f64) -> f64>(f : F, a : f64, b : f64) -> (f64, f64) {
+    let diff = |x : f64, g : &dyn Fn(f64) -> f64| {
+        let lo = (x - H).max(0.);
+        let hi = (x + H).min(1.);
+        (g(hi) - g(lo)) / (hi - lo)
+    };
+    (diff(a, &|a| f(a, b)), diff(b, &|b| f(a, b)))
+}
+
+/// Derivative of `abs(x)`, zero at the kink.
+fn d_abs(x : f64) -> f64 {
+    if x > 0. { 1. } else if x < 0. { -1. } else { 0. }
+}
+
+/// The semantics this crate started with.
+///
+/// `Xor: abs(a-b)`, `And: a*b`, `Or: min[ sqrt(a)+sqrt(b) , 1 ]`, `Not: 1-a`
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Standard;
+
+impl FuzzySemantics for Standard {
+    fn xor(&self, a : f64, b : f64) -> f64 {
+        (a - b).abs()
+    }
+    fn and(&self, a : f64, b : f64) -> f64 {
+        a * b
+    }
+    fn or(&self, a : f64, b : f64) -> f64 {
+        (a.sqrt() + b.sqrt()).min(1.)
+    }
+    fn d_xor(&self, a : f64, b : f64) -> (f64, f64) {
+        (d_abs(a - b), -d_abs(a - b))
+    }
+    fn d_and(&self, a : f64, b : f64) -> (f64, f64) {
+        (b, a)
+    }
+    fn d_or(&self, a : f64, b : f64) -> (f64, f64) {
+        if a.sqrt() + b.sqrt() >= 1. {
+            (0., 0.)
+        } else {
+            (0.5 / a.max(SQRT_EPS).sqrt(), 0.5 / b.max(SQRT_EPS).sqrt())
+        }
+    }
+}
+
+/// Probability of the result being 1 if the operands are independent random bits
+/// that are 1 with probability `a` and `b`.
+///
+/// `Xor: a+b-2ab`, `And: a*b`, `Or: a+b-ab`, `Not: 1-a`
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Probabilistic;
+
+impl FuzzySemantics for Probabilistic {
+    fn xor(&self, a : f64, b : f64) -> f64 {
+        a + b - 2. * a * b
+    }
+    fn and(&self, a : f64, b : f64) -> f64 {
+        a * b
+    }
+    fn or(&self, a : f64, b : f64) -> f64 {
+        a + b - a * b
+    }
+    fn d_xor(&self, a : f64, b : f64) -> (f64, f64) {
+        (1. - 2. * b, 1. - 2. * a)
+    }
+    fn d_and(&self, a : f64, b : f64) -> (f64, f64) {
+        (b, a)
+    }
+    fn d_or(&self, a : f64, b : f64) -> (f64, f64) {
+        (1. - b, 1. - a)
+    }
+}
+
+/// Łukasiewicz t-norm and t-conorm. Xor is `(a & !b) | (!a & b)` with these.
+///
+/// `Xor: abs(a-b)`, `And: max[ a+b-1 , 0 ]`, `Or: min[ a+b , 1 ]`, `Not: 1-a`
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Lukasiewicz;
+
+impl FuzzySemantics for Lukasiewicz {
+    fn xor(&self, a : f64, b : f64) -> f64 {
+        self.or(self.and(a, 1. - b), self.and(1. - a, b))
+    }
+    fn and(&self, a : f64, b : f64) -> f64 {
+        (a + b - 1.).max(0.)
+    }
+    fn or(&self, a : f64, b : f64) -> f64 {
+        (a + b).min(1.)
+    }
+    fn d_xor(&self, a : f64, b : f64) -> (f64, f64) {
+        // equals abs(a-b) on [0,1]
+        (d_abs(a - b), -d_abs(a - b))
+    }
+    fn d_and(&self, a : f64, b : f64) -> (f64, f64) {
+        if a + b > 1. { (1., 1.) } else { (0., 0.) }
+    }
+    fn d_or(&self, a : f64, b : f64) -> (f64, f64) {
+        if a + b < 1. { (1., 1.) } else { (0., 0.) }
+    }
+}
+
+/// Gödel (min/max) t-norm and t-conorm. Xor is `(a & !b) | (!a & b)` with these.
+///
+/// `Xor: max[ min(a,1-b) , min(1-a,b) ]`, `And: min(a,b)`, `Or: max(a,b)`, `Not: 1-a`
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Goedel;
+
+impl FuzzySemantics for Goedel {
+    fn xor(&self, a : f64, b : f64) -> f64 {
+        self.or(self.and(a, 1. - b), self.and(1. - a, b))
+    }
+    fn and(&self, a : f64, b : f64) -> f64 {
+        a.min(b)
+    }
+    fn or(&self, a : f64, b : f64) -> f64 {
+        a.max(b)
+    }
+    fn d_xor(&self, a : f64, b : f64) -> (f64, f64) {
+        if a.min(1. - b) >= (1. - a).min(b) {
+            if a <= 1. - b { (1., 0.) } else { (0., -1.) }
+        } else if 1. - a <= b {
+            (-1., 0.)
+        } else {
+            (0., 1.)
+        }
+    }
+    fn d_and(&self, a : f64, b : f64) -> (f64, f64) {
+        if a <= b { (1., 0.) } else { (0., 1.) }
+    }
+    fn d_or(&self, a : f64, b : f64) -> (f64, f64) {
+        if a >= b { (1., 0.) } else { (0., 1.) }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::u::U;
+    use ::sha::Sha256;
+
+    /// A user defined semantics, as a downstream crate would write it.
+    struct Custom;
+
+    impl FuzzySemantics for Custom {
+        fn xor(&self, a : f64, b : f64) -> f64 {
+            ((a - b) * (a - b)).sqrt()
+        }
+        fn and(&self, a : f64, b : f64) -> f64 {
+            a * b
+        }
+        fn or(&self, a : f64, b : f64) -> f64 {
+            1. - (1. - a) * (1. - b)
+        }
+    }
+
+    fn bits_are_boolean(bits : &[f64]) {
+        for b in bits {
+            assert!(*b == 0. || *b == 1., "{} is not boolean", b);
+        }
+    }
+
+    /// Shared test suite every semantics has to pass.
+    fn agrees_with_boolean_logic<S : FuzzySemantics>(s : &S) {
+        for &a in [false, true].iter() {
+            for &b in [false, true].iter() {
+                let (fa, fb) = (a as u8 as f64, b as u8 as f64);
+                assert_eq!(s.xor(fa, fb), (a ^ b) as u8 as f64);
+                assert_eq!(s.and(fa, fb), (a & b) as u8 as f64);
+                assert_eq!(s.or(fa, fb), (a | b) as u8 as f64);
+            }
+            assert_eq!(s.not(a as u8 as f64), !a as u8 as f64);
+        }
+
+        let (a1, a2) = (11241257u32, 3723573424u32);
+        let u1 = U::new_symbolic();
+        let u2 = U::new_symbolic();
+        u1.set_bytes(&[(a1 >> 24) as u8, (a1 >> 16) as u8, (a1 >> 8) as u8, a1 as u8]);
+        u2.set_bytes(&[(a2 >> 24) as u8, (a2 >> 16) as u8, (a2 >> 8) as u8, a2 as u8]);
+        let sum = &u1 + &u2;
+        let mut bits = vec![];
+        sum.evaluate_with(&mut bits, s);
+        bits_are_boolean(&bits);
+        assert_eq!(sum.eval_to_u32_with(s), a1.wrapping_add(a2));
+
+        let data = vec![U::new_symbolic()];
+        data[0].set_byte(b'a', 0);
+        data[0].set_byte(b'\n', 1);
+        let sha = Sha256::new(data, 16);
+        bits_are_boolean(&sha.evaluate_with(s));
+        assert_eq!(&::util::hex(&sha.eval_to_u32_with(s)),
+                   "87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7");
+    }
+
+    #[test]
+    fn standard() {
+        agrees_with_boolean_logic(&Standard);
+    }
+
+    #[test]
+    fn probabilistic() {
+        agrees_with_boolean_logic(&Probabilistic);
+        assert_eq!(Probabilistic.xor(0.5, 0.5), 0.5);
+    }
+
+    #[test]
+    fn lukasiewicz() {
+        agrees_with_boolean_logic(&Lukasiewicz);
+        assert_eq!(Lukasiewicz.and(0.5, 0.5), 0.);
+    }
+
+    #[test]
+    fn goedel() {
+        agrees_with_boolean_logic(&Goedel);
+        assert_eq!(Goedel.or(0.25, 0.5), 0.5);
+    }
+
+    #[test]
+    fn custom() {
+        agrees_with_boolean_logic(&Custom);
+    }
+
+    fn close(x : (f64, f64), y : (f64, f64)) -> bool {
+        (x.0 - y.0).abs() < 1e-4 && (x.1 - y.1).abs() < 1e-4
+    }
+
+    /// Compares the exact partial derivatives with finite differences at
+    /// points away from the kinks of all built in semantics.
+    fn derivatives_match<S : FuzzySemantics>(s : &S) {
+        let points = [(0.1, 0.3), (0.3, 0.1), (0.2, 0.7), (0.65, 0.8), (0.9, 0.15)];
+        for &(a, b) in points.iter() {
+            assert!(close(s.d_xor(a, b), finite_differences(|a, b| s.xor(a, b), a, b)));
+            assert!(close(s.d_and(a, b), finite_differences(|a, b| s.and(a, b), a, b)));
+            assert!(close(s.d_or(a, b), finite_differences(|a, b| s.or(a, b), a, b)));
+            assert_eq!(s.d_not(a), -1.);
+        }
+    }
+
+    #[test]
+    fn derivatives() {
+        derivatives_match(&Standard);
+        derivatives_match(&Probabilistic);
+        derivatives_match(&Lukasiewicz);
+        derivatives_match(&Goedel);
+        assert!(close(Custom.d_or(0.2, 0.5), (0.5, 0.8)));
+    }
+
+    #[test]
+    fn semantics_differ() {
+        let a = U::new_symbolic();
+        for b in a.bits().iter() {
+            b.set(0.3);
+        }
+        let sum = &a + &a.rotate_right(3);
+        let (mut standard, mut goedel) = (vec![], vec![]);
+        sum.reset();
+        sum.evaluate_with(&mut standard, &Standard);
+        sum.reset();
+        sum.evaluate_with(&mut goedel, &Goedel);
+        assert!(standard != goedel);
+    }
+}
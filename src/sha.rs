@@ -2,10 +2,22 @@
 
 use ::util::hex;
 use ::u::U;
-use ::term::{Term};
+use ::term::{RTerm, TermGraph, Simplifications};
+use ::semantics::{FuzzySemantics, Standard};
+use ::dual::Dual;
+use ::batch::Batch;
+use ::bitslice::BitSlice;
+use ::frozen::FrozenGraph;
+use ::word::{WordGraph, WordId};
+use ::anf::Anf;
+use ::montecarlo::MonteCarlo;
+use ::interval::{Interval, Intervals};
+use ::dependency::{Bitmap, DependencyMatrix};
+use ::util::f64bits_to_u32;
 use arrayvec::ArrayVec;
 use std::cmp::max;
 use std::fmt;
+use std::io::{self, Write};
 
 
 /// This struct does a lazy SHA-256 hash calculation with fuzzy `f64` bits.
@@ -34,12 +46,21 @@ impl Sha256 {
     ///
     /// `len_message_in_last_u_in_bits` is the number of bits in `data.last()` that
     /// are part of the input data. All following bits in `data.last()` will be
-    /// ignored (and replaced).
+    /// ignored (and replaced). An empty `data` is the empty message, then
+    /// `len_message_in_last_u_in_bits` is ignored.
     ///
     /// You can access `data` afterwards using the `data` field.
     ///
     /// This function just does SHA-256, but with `U`s instead of `u32`s.
-    pub fn new(mut data : Vec<U>, len_message_in_last_u_in_bits : usize) -> Sha256 {
+    pub fn new(data : Vec<U>, len_message_in_last_u_in_bits : usize) -> Sha256 {
+        Sha256::with_rounds(data, len_message_in_last_u_in_bits, 64)
+    }
+
+    /// Same as `new()`, but the compression function only does the first
+    /// `rounds` of its 64 rounds. This reduced-round variant is much easier
+    /// to attack, e.g. with a SAT solver, than the real SHA-256.
+    pub fn with_rounds(mut data : Vec<U>, len_message_in_last_u_in_bits : usize, rounds : usize) -> Sha256 {
+        assert!(rounds <= 64);
 
         let mut h0 = U::from_const(0x6a09e667u32);
         let mut h1 = U::from_const(0xbb67ae85u32);
@@ -58,7 +79,10 @@ impl Sha256 {
         // WIKI:     length (modulo 512 in bits) is 448.
 
         assert!(len_message_in_last_u_in_bits <= 32);
-        let total_msg_len = (data.len() - 1) * 32 + len_message_in_last_u_in_bits;
+        let total_msg_len = match data.len() {
+            0 => 0,
+            n => (n - 1) * 32 + len_message_in_last_u_in_bits,
+        };
         let msg_len_modulo = total_msg_len % 512;
 
         let mut bits_to_add = if msg_len_modulo <= 448 { 448 - msg_len_modulo }
@@ -67,19 +91,20 @@ impl Sha256 {
         // 448 is multiple of 32, so add 32 bit chunks aka `U`s ...
 
         // ... but first put `Constant` `Term`s into the inchoate one if there is one
-        if len_message_in_last_u_in_bits == 32 && bits_to_add > 0 {
+        if (data.is_empty() || len_message_in_last_u_in_bits == 32) && bits_to_add > 0 {
             assert!(bits_to_add >= 32);
             data.push(U::from_const(0x8000_0000));
             bits_to_add -= 32;
         } else {
             assert!(len_message_in_last_u_in_bits > 0);
             assert!(bits_to_add > 0);
-            let mut u = data.pop().unwrap();
-            u.bits[32 - len_message_in_last_u_in_bits - 1] = Term::c1();
-            for i in 0..(32 - len_message_in_last_u_in_bits - 1) {
-                u.bits[i] = Term::c0();
-            }
-            data.push(u);
+            let u = data.pop().unwrap();
+            let pad_bit = 32 - len_message_in_last_u_in_bits - 1;
+            // (u & message mask) ^ padding, the masked bits are zero, so ^ works like |.
+            // Constant folding keeps the message bits and puts constants below.
+            let mask = U::from_const(!0u32 << (pad_bit + 1));
+            let padding = U::from_const(1u32 << pad_bit);
+            data.push(u.and(&mask).xor(&padding));
             bits_to_add -= 32 - len_message_in_last_u_in_bits;
         }
         assert!(bits_to_add % 32 == 0);
@@ -93,11 +118,11 @@ impl Sha256 {
         // WIKI: append length of message (without the '1' bit or padding), in bits, as 64-bit big-endian integer
         // WIKI:     (this will make the entire post-processed length a multiple of 512 bits)
 
-        assert!(total_msg_len <= u32::max_value() as usize);
+        assert!(total_msg_len <= u32::MAX as usize);
         data.push(U::from_const(0u32));
         data.push(U::from_const(total_msg_len as u32));
 
-        assert!(data.len() % 16 == 0);
+        assert!(data.len().is_multiple_of(16));
 
         for chunk in data.chunks(16) {
             // WIKI: create a 64-entry message schedule array w[0..63] of 32-bit words
@@ -114,7 +139,7 @@ impl Sha256 {
             // WIKI:     s0 := (w[i-15] rightrotate 7) xor (w[i-15] rightrotate 18) xor (w[i-15] rightshift 3)
             // WIKI:     s1 := (w[i-2] rightrotate 17) xor (w[i-2] rightrotate 19) xor (w[i-2] rightshift 10)
             // WIKI:     w[i] := w[i-16] + s0 + w[i-7] + s1
-            for i in 16..64 {
+            for i in 16..rounds {
                 let s0 = (w[i-15].rotate_right(7) ^ w[i-15].rotate_right(18)) ^ w[i-15].shift_right(3);
                 let s1 = (w[i-2].rotate_right(17) ^ w[i-2].rotate_right(19)) ^ w[i-2].shift_right(10);
                 let nextw = &w[i-16] + &s0 + &w[i-7] + &s1;
@@ -132,7 +157,7 @@ impl Sha256 {
             let mut g = h6.clone();
             let mut h = h7.clone();
 
-            for i in 0..64 {
+            for i in 0..rounds {
                 // WIKI: S1 := (e rightrotate 6) xor (e rightrotate 11) xor (e rightrotate 25)
                 let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
                 // WIKI: ch := (e and f) xor ((not e) and g)
@@ -168,7 +193,7 @@ impl Sha256 {
 
 
         Sha256 {
-            data : data,
+            data,
             digest : vec![h0, h1, h2, h3, h4, h5, h6, h7],
             input_data_len_in_bits : total_msg_len,
         }
@@ -184,10 +209,15 @@ impl Sha256 {
 
     /// Evaluates the digest/hash result into `u32`s by rounding the `f64` bits.
     pub fn eval_to_u32(&self) -> ArrayVec<[u32; 8]> {
+        self.eval_to_u32_with(&Standard)
+    }
+
+    /// Same as `eval_to_u32()`, but with the given `FuzzySemantics`.
+    pub fn eval_to_u32_with<S : FuzzySemantics + ?Sized>(&self, sem : &S) -> ArrayVec<[u32; 8]> {
         self.reset();
         let mut digest = ArrayVec::new();
         for h in self.digest.iter() {
-            digest.push(h.eval_to_u32());
+            digest.push(h.eval_to_u32_with(sem));
         }
         digest
     }
@@ -203,14 +233,219 @@ impl Sha256 {
     /// returnval[0] is the MSBit of the first byte. The first byte corresponds
     /// to the start of the ascii hash representation.
     pub fn evaluate(&self) -> Vec<f64> {
+        self.evaluate_with(&Standard)
+    }
+
+    /// Same as `evaluate()`, but with the given `FuzzySemantics`.
+    pub fn evaluate_with<S : FuzzySemantics + ?Sized>(&self, sem : &S) -> Vec<f64> {
         self.reset();
         let mut out = Vec::with_capacity(256);
         for u in self.digest.iter() {
-            u.evaluate(&mut out);
+            u.evaluate_with(&mut out, sem);
+        }
+        out
+    }
+
+    /// Evaluates the digest for every lane of `batch` with one walk over the
+    /// `Term`s. Returns one vector of 256 `f64`s per lane, each in the same
+    /// order as returned by `evaluate()`.
+    pub fn evaluate_batch(&self, batch : &Batch) -> Vec<Vec<f64>> {
+        self.evaluate_batch_with(batch, &Standard)
+    }
+
+    /// Same as `evaluate_batch()`, but with the given `FuzzySemantics`.
+    pub fn evaluate_batch_with<S : FuzzySemantics + ?Sized>(&self, batch : &Batch, sem : &S) -> Vec<Vec<f64>> {
+        let roots = self.digest_roots();
+        let bits = TermGraph::with(|g| g.evaluate_batch(&roots, batch, sem));
+        (0..batch.lanes()).map(|lane| bits.iter().map(|b| b[lane]).collect()).collect()
+    }
+
+    /// Evaluates the digest for every lane of `batch` into `u32`s by rounding
+    /// the `f64` bits.
+    pub fn eval_to_u32_batch(&self, batch : &Batch) -> Vec<ArrayVec<[u32; 8]>> {
+        self.evaluate_batch(batch).iter().map(|d| f64bits_to_u32(d)).collect()
+    }
+
+    /// The `Term`s of the digest bits, in the same order as returned by `evaluate()`.
+    fn digest_roots(&self) -> Vec<RTerm> {
+        self.digest.iter().flat_map(|u| u.bits().iter().rev().cloned()).collect()
+    }
+
+    /// Copies the `Term`s of the digest into a `FrozenGraph` that can be
+    /// evaluated on other threads. Its roots are the digest bits, in the same
+    /// order as returned by `evaluate()`.
+    pub fn freeze(&self) -> FrozenGraph {
+        TermGraph::with(|g| g.freeze(&self.digest_roots()))
+    }
+
+    /// Calculates the real SHA-256 hash of all 64 lanes of `bits` with one
+    /// walk over the `Term`s. Symbols without lane values must be set to
+    /// exactly 0.0 or 1.0.
+    pub fn eval_to_u32_bits(&self, bits : &BitSlice) -> Vec<ArrayVec<[u32; 8]>> {
+        let roots = self.digest_roots();
+        let words = TermGraph::with(|g| g.evaluate_bits(&roots, bits));
+        (0..BitSlice::LANES).map(|lane| {
+            words.chunks(32).map(|w| w.iter().fold(0u32, |o, b| o << 1 | (b >> lane & 1) as u32)).collect()
+        }).collect()
+    }
+
+    /// Searches all assignments to the `free` input symbols for one whose hash
+    /// starts with the bits `target_prefix` (MSB first, like `evaluate()`).
+    /// All other input symbols must be set to exactly 0.0 or 1.0.
+    ///
+    /// Returns the assignment, bit `i` being the value of `free[i]`.
+    /// See `TermGraph::brute_force()`.
+    pub fn brute_force(&self, free : &[RTerm], target_prefix : &[bool]) -> Option<u64> {
+        assert!(target_prefix.len() <= 256);
+        let roots = self.digest_roots();
+        TermGraph::with(|g| g.brute_force(&roots[..target_prefix.len()], target_prefix, free))
+    }
+
+    /// Exact probability of each digest bit being 1 if each input bit is 1
+    /// with the probability it has been `set()` to, in the same order as
+    /// `evaluate()`. See `TermGraph::signal_probabilities()`.
+    pub fn signal_probabilities(&self, max_leaves : usize) -> Vec<f64> {
+        TermGraph::with(|g| g.signal_probabilities(&self.digest_roots(), max_leaves))
+    }
+
+    /// Bounds of each digest bit, in the same order as `evaluate()`, for
+    /// input bits within `intervals`. See `TermGraph::evaluate_intervals()`.
+    pub fn evaluate_intervals(&self, intervals : &Intervals) -> Vec<Interval> {
+        self.evaluate_intervals_with(intervals, &Standard)
+    }
+
+    /// Same as `evaluate_intervals()`, but with the given `FuzzySemantics`.
+    pub fn evaluate_intervals_with<S : FuzzySemantics + ?Sized>(&self, intervals : &Intervals, sem : &S) -> Vec<Interval> {
+        TermGraph::with(|g| g.evaluate_intervals(&self.digest_roots(), intervals, sem))
+    }
+
+    /// The digest bits that round to the same value for all input bits
+    /// within `intervals`, in the same order as `evaluate()`. Unset input bits
+    /// may be anything. See `Interval::determined()`.
+    pub fn determined_bits(&self, intervals : &Intervals) -> Vec<Option<bool>> {
+        self.evaluate_intervals(intervals).iter().map(|i| i.determined()).collect()
+    }
+
+    /// The digest bits in three-valued logic, in the same order as
+    /// `evaluate()`: `None` if a bit depends on the unset input bits. See
+    /// `TermGraph::evaluate_ternary()`.
+    pub fn evaluate_ternary(&self) -> Vec<Option<bool>> {
+        TermGraph::with(|g| g.evaluate_ternary(&self.digest_roots()))
+    }
+
+    /// A copy of this `Sha256` with the input bits that have been `set()`
+    /// folded into the digest as constants, see `TermGraph::partial_evaluate()`.
+    /// With a known message prefix, the digest of the copy is a much smaller
+    /// circuit whose only inputs are the unset bits of the suffix.
+    ///
+    /// `data` is shared with this instance. The digest has no word-level
+    /// provenance.
+    pub fn partial_evaluate(&self) -> Sha256 {
+        let roots : Vec<RTerm> = self.digest.iter().flat_map(|u| u.bits().iter().cloned()).collect();
+        let new_roots = TermGraph::with_mut(|g| g.partial_evaluate(&roots));
+        let digest = new_roots.chunks(32).map(U::from_bits).collect();
+        Sha256 {
+            data : self.data.clone(),
+            input_data_len_in_bits : self.input_data_len_in_bits,
+            digest,
+        }
+    }
+
+    /// The `Symbol`s of the message, MSBit of the first byte first. These are
+    /// the inputs of `dependencies()`, `reach()` and `dependency_matrix()`.
+    pub fn message_bits(&self) -> Vec<RTerm> {
+        self.data.iter().flat_map(|u| u.bits().iter().rev()).take(self.input_data_len_in_bits).cloned().collect()
+    }
+
+    /// The message bits each digest bit depends on, in the same order as
+    /// `evaluate()`. See `TermGraph::dependencies()`.
+    pub fn dependencies(&self) -> Vec<Bitmap> {
+        TermGraph::with(|g| g.dependencies(&self.digest_roots(), &self.message_bits()))
+    }
+
+    /// The digest bits that message bit `j` reaches, see `message_bits()`.
+    /// For the diffusion after `r` rounds, use `with_rounds()`.
+    pub fn reach(&self, j : usize) -> Bitmap {
+        let input = self.message_bits()[j];
+        TermGraph::with(|g| g.reach(&self.digest_roots(), &[input])).pop().unwrap()
+    }
+
+    /// The dependencies of all 256 digest bits on all message bits.
+    pub fn dependency_matrix(&self) -> DependencyMatrix {
+        DependencyMatrix::new(self.dependencies(), self.input_data_len_in_bits)
+    }
+
+    /// Samples boolean inputs with the input bit values as probabilities and
+    /// counts the ones of each digest bit, in the same order as `evaluate()`.
+    /// See `MonteCarlo::sample()`.
+    pub fn monte_carlo(&self, samples : usize, seed : u64) -> MonteCarlo {
+        MonteCarlo::sample(&self.digest_roots(), samples, seed)
+    }
+
+    /// The algebraic normal form of each digest bit in the `free` input
+    /// symbols, in the same order as `evaluate()`. All other input symbols
+    /// must be set. See `TermGraph::anf()`.
+    ///
+    /// Returns `None` if a `Term` needs more than `max_monomials` monomials.
+    pub fn anf(&self, free : &[RTerm], max_monomials : usize) -> Option<Vec<Anf>> {
+        TermGraph::with(|g| g.anf(&self.digest_roots(), free, max_monomials))
+    }
+
+    /// Incremental version of `evaluate()`, for use after `evaluate()` if only
+    /// the input bits in `changed` have been `set()` to new values since.
+    /// Only the `Term`s depending on these bits are evaluated again.
+    /// See `TermGraph::update()`.
+    ///
+    /// Returns the updated 256 `f64`s, in the same order as `evaluate()`.
+    pub fn evaluate_changed(&self, changed : &[RTerm]) -> Vec<f64> {
+        self.evaluate_changed_with(changed, &Standard)
+    }
+
+    /// Same as `evaluate_changed()`, but with the given `FuzzySemantics`.
+    /// It must be the same as used by the previous evaluation.
+    pub fn evaluate_changed_with<S : FuzzySemantics + ?Sized>(&self, changed : &[RTerm], sem : &S) -> Vec<f64> {
+        TermGraph::with_mut(|g| g.update(changed, sem));
+        let mut out = Vec::with_capacity(256);
+        for u in self.digest.iter() {
+            u.evaluate_with(&mut out, sem);
         }
         out
     }
 
+    /// Evaluates the digest with dual numbers: Returns the 256 digest bits in
+    /// the same order as `evaluate()`, each with its directional derivative
+    /// along `direction`. See `TermGraph::evaluate_dual()`.
+    ///
+    /// Use e.g. the `U::byte_bits()` of one input byte as direction to see
+    /// which digest bits a change of that byte reaches.
+    pub fn evaluate_dual(&self, direction : &[(RTerm, f64)]) -> Vec<Dual> {
+        self.evaluate_dual_with(direction, &Standard)
+    }
+
+    /// Same as `evaluate_dual()`, but with the given `FuzzySemantics`.
+    pub fn evaluate_dual_with<S : FuzzySemantics + ?Sized>(&self, direction : &[(RTerm, f64)], sem : &S) -> Vec<Dual> {
+        let roots = self.digest_roots();
+        TermGraph::with(|g| g.evaluate_dual(&roots, direction, sem))
+    }
+
+    /// Gradient of a scalar loss with respect to every `Symbol`, calculated
+    /// with one backward pass through the `Term`s. `d_digest` holds the
+    /// derivatives of the loss with respect to the 256 digest bits, in the
+    /// same order as returned by `evaluate()`.
+    ///
+    /// The result is indexed by the number in `TermType::Symbol`.
+    pub fn gradient(&self, d_digest : &[f64]) -> Vec<f64> {
+        self.gradient_with(d_digest, &Standard)
+    }
+
+    /// Same as `gradient()`, but with the given `FuzzySemantics`.
+    pub fn gradient_with<S : FuzzySemantics + ?Sized>(&self, d_digest : &[f64], sem : &S) -> Vec<f64> {
+        assert_eq!(d_digest.len(), 256);
+        self.reset();
+        let roots = self.digest_roots();
+        TermGraph::with_mut(|g| g.gradient(&roots, d_digest, sem))
+    }
+
     /// Returns the number of `Term`s that were created to represent this
     /// instance of the SHA-256 algorithm. The number depends heavily on the
     /// length of the input data.
@@ -223,10 +458,10 @@ impl Sha256 {
     /// of the tree of each bit of the digest.
     pub fn nr_of_terms_flattened(&self) -> usize {
         self.reset();
-        self.digest.iter().map(|u| u.nr_of_terms_flattened()).sum()
+        self.digest.iter().map(|u| u.nr_of_terms_flattened()).fold(0, usize::saturating_add)
     }
 
-    /// Max stack size needed when evaluating each bit of the digest recursively.
+    /// Max size of the explicit stack needed when evaluating each bit of the digest.
     ///
     /// It also returns the maximum logic depth.
     ///
@@ -240,6 +475,45 @@ impl Sha256 {
                               })
     }
 
+    /// Applies the algebraic rewrite rules of the `TermGraph` to the whole
+    /// digest and replaces the digest with the simplified `Term`s. The input
+    /// `data` stays the same.
+    ///
+    /// Returns how many `Term`s each rule eliminated.
+    pub fn simplify(&mut self) -> Simplifications {
+        U::simplify(&mut self.digest)
+    }
+
+    /// The word-level expressions of the digest, if all digest `U`s have one.
+    fn digest_words(&self) -> Option<Vec<WordId>> {
+        self.digest.iter().map(|u| u.word()).collect()
+    }
+
+    /// Writes the digest computation at word level, see `WordGraph::write()`.
+    /// `out0` to `out7` are the digest words.
+    ///
+    /// Fails with `InvalidInput` if a digest `U` has no word-level provenance.
+    pub fn write_words<W : Write>(&self, out : &mut W) -> io::Result<()> {
+        let roots = self.digest_words()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Digest without word-level provenance."))?;
+        WordGraph::with(|w| w.write(&roots, out))
+    }
+
+    /// Simplifies the digest at word level and replaces the digest with the
+    /// bit-blasted result, see `WordGraph::simplify()`. The input `data`
+    /// stays the same.
+    ///
+    /// Returns the number of word-level operations in the cone of the digest
+    /// before and after. Panics if a digest `U` has no word-level provenance.
+    pub fn simplify_words(&mut self) -> (usize, usize) {
+        let roots = self.digest_words().expect("Digest without word-level provenance.");
+        let count = |roots : &[WordId]| WordGraph::with(|w| w.cone(roots).iter().filter(|n| **n).count());
+        let before = count(&roots);
+        let new_roots = WordGraph::with_mut(|w| w.simplify(&roots));
+        self.digest = WordGraph::bit_blast(&new_roots);
+        (before, count(&new_roots))
+    }
+
     /// Returns a String describing the statistics. Same as debug print.
     pub fn statistics(&self) -> String {
         format!("{:?}", self)
@@ -248,6 +522,15 @@ impl Sha256 {
 }
 
 
+impl PartialEq for Sha256 {
+    /// Two instances are equal if their digests are made of the same `Term`s.
+    /// Thanks to the hash-consed `TermGraph` this is the case if they have
+    /// been built from the same input `U`s.
+    fn eq(&self, other : &Sha256) -> bool {
+        self.digest == other.digest
+    }
+}
+
 impl fmt::Debug for Sha256 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let nr_of_term = self.nr_of_terms();
@@ -255,7 +538,7 @@ impl fmt::Debug for Sha256 {
         let (max_logic_depth, max_stacksize) = self.max_logic_depth_and_max_stack_size();
         self.reset();
 
-        write!(f, "Input data: {} bytes and {} bits = {} bits\nTotal RTerms: {}\nMaximum depth of logic elements: {}\nNeeded recursion depth for evaluation: {}\nFlattened tree size: {}",
+        write!(f, "Input data: {} bytes and {} bits = {} bits\nTotal RTerms: {}\nMaximum depth of logic elements: {}\nNeeded stack size for evaluation: {}\nFlattened tree size: {}",
             self.input_data_len_in_bits / 8,
             self.input_data_len_in_bits % 8,
             self.input_data_len_in_bits,
@@ -271,6 +554,7 @@ impl fmt::Debug for Sha256 {
 mod tests {
     use super::*;
     use ::u::U;
+    use ::term::TermGraph;
     use test::Bencher;
 
     #[test]
@@ -291,6 +575,14 @@ mod tests {
         assert_eq!(&s.hex(), "87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7");
     }
 
+    #[test]
+    fn empty() {
+        let s = Sha256::new(vec![], 0);
+        // echo -n '' | sha256sum
+        assert_eq!(&s.hex(), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(s.input_data_len_in_bits, 0);
+    }
+
     #[test]
     fn test_sha_long() {
         let data : Vec<U> = (0..16u8).map(|i| {[i*4%16 + b'a',
@@ -325,6 +617,89 @@ mod tests {
     }
 
 
+    #[test]
+    fn multi_block_on_small_stack() {
+        // 220 bytes -> 4 blocks of 512 bits. Building and evaluating needs
+        // no recursion, so a tiny stack is enough.
+        let hex = ::std::thread::Builder::new().stack_size(64 * 1024).spawn(|| {
+            let bytes : Vec<u8> = (0..220u32).map(|i| ((i * 7 + 3) % 256) as u8).collect();
+            let data : Vec<U> = bytes.chunks(4).map(|b4| {
+                let u = U::new_symbolic();
+                u.set_bytes(b4);
+                u
+            }).collect();
+            let s = Sha256::new(data, 32);
+            println!("{:?}", s);
+            s.hex()
+        }).unwrap().join().unwrap();
+        assert_eq!(&hex, "a942e3d70d15f2a519007eee4e580bf17288cfbd5fd6aab8a5d2c24df1de526c");
+    }
+
+    #[test]
+    fn evaluate_changed() {
+        let data = vec![U::new_symbolic(), U::new_symbolic()];
+        for (i, b) in data[0].bits().iter().chain(data[1].bits().iter()).enumerate() {
+            b.set(if i % 3 == 0 { 0.3 } else { (i % 2) as f64 });
+        }
+        let changed = [data[0].bits()[5], data[1].bits()[30], data[1].bits()[31]];
+        let s = Sha256::new(data, 32);
+        s.evaluate();
+        changed[0].set(0.8);
+        changed[1].set(0.);
+        changed[2].set(1.);
+        let incremental = s.evaluate_changed(&changed);
+        assert_eq!(incremental, s.evaluate());
+
+        s.data[0].set_bytes(b"abcd");
+        s.data[1].set_bytes(b"efgh");
+        s.evaluate();
+        s.data[1].set_byte(b'x', 2);
+        let changed : Vec<_> = s.data[1].byte_bits(2).to_vec();
+        assert_eq!(::util::f64bits_to_u32(&s.evaluate_changed(&changed)).as_ref(),
+                   &::util::dehex("1eaca88ed9573aaea1432fb19a569c8ccb7a5de73ba9fbc6412d08e5b3284d61")[..]);
+    }
+
+    #[test]
+    fn rebuild_is_equal() {
+        let data = vec![U::new_symbolic()];
+        let s1 = Sha256::new(data.clone(), 8);
+        let nr_of_terms = TermGraph::with(|g| g.len());
+        let s2 = Sha256::new(data, 8);
+        assert_eq!(TermGraph::with(|g| g.len()), nr_of_terms);
+        assert!(s1 == s2);
+        let s3 = Sha256::new(vec![U::new_symbolic()], 8);
+        assert!(s1 != s3);
+    }
+
+    #[test]
+    fn simplify() {
+        let data = vec![U::new_symbolic()];
+        data[0].set_byte(b'a', 0);
+        let mut s = Sha256::new(data, 8);
+        let nr_of_terms = s.nr_of_terms();
+        let simplifications = s.simplify();
+        println!("{}", simplifications);
+        assert!(simplifications.total() > 0);
+        assert!(s.nr_of_terms() <= nr_of_terms - simplifications.total());
+        assert_eq!(&s.hex(), "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb");
+    }
+
+    #[test]
+    fn simplify_words() {
+        let data = vec![U::new_symbolic()];
+        data[0].set_bytes(b"a\n\0\0");
+        let mut s = Sha256::new(data, 16);
+        let mut listing = Vec::new();
+        s.write_words(&mut listing).unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+        assert!(listing.contains(" >>> 6\n"));
+        assert!(listing.ends_with(&format!("out7 = w{}\n", s.digest[7].word().unwrap().index())));
+        // The first round mixes the constant initial hash values only.
+        let (before, after) = s.simplify_words();
+        assert!(after < before);
+        assert_eq!(&s.hex(), "87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7");
+    }
+
     #[bench]
     fn bench_sha_one_byte(b: &mut Bencher) {
 
@@ -0,0 +1,266 @@
+//! `smt::Smt`: Export of `U` computations to SMT-LIB2.
+
+use ::term::{RTerm, TermType, TermGraph};
+use ::word::{WordGraph, WordId, WordOp};
+use ::u::U;
+use ::sha::Sha256;
+use ::util::{dehex, u32_to_f64bits};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Write};
+
+/// A satisfiability problem over the output `U`s of a computation, for
+/// SMT solvers.
+///
+/// It can be written at bit level, with one `Bool` per `Term`, or at word
+/// level, with one `(_ BitVec 32)` per word-level expression of the `U`s
+/// (see `word::WordGraph`). Both contain the same constraints on the input
+/// bits and output bits.
+#[derive(Clone, Default, Debug)]
+pub struct Smt {
+    /// Input bit position and `Symbol`.
+    inputs : Vec<(usize, RTerm)>,
+    outputs : Vec<U>,
+    fixed_inputs : Vec<(RTerm, bool)>,
+    /// Output bit index, see `fix_outputs()`, and value.
+    fixed_outputs : Vec<(usize, bool)>,
+}
+
+impl Smt {
+
+    /// The problem for `outputs`, with the `Symbol`s in `inputs` as input bits,
+    /// numbered from the MSBit of the first `U`.
+    pub fn new(inputs : &[U], outputs : &[U]) -> Smt {
+        let mut smt = Smt::default();
+        for (w, u) in inputs.iter().enumerate() {
+            for (j, b) in u.bits().iter().rev().enumerate() {
+                if let TermType::Symbol(_) = b.term_type() {
+                    smt.inputs.push((w * 32 + j, *b));
+                }
+            }
+        }
+        smt.outputs = outputs.to_vec();
+        smt
+    }
+
+    /// The problem for the digest of `sha`, with its message as input.
+    pub fn sha(sha : &Sha256) -> Smt {
+        Smt::new(&sha.data, &sha.digest)
+    }
+
+    /// Fixes the outputs to the hash `target_hash` (64 hex characters).
+    pub fn fix_digest(&mut self, target_hash : &str) {
+        let bits : Vec<bool> = u32_to_f64bits(dehex(target_hash).as_ref()).iter().map(|b| *b == 1.).collect();
+        self.fix_outputs(&bits);
+    }
+
+    /// Fixes the first `bits.len()` output bits, MSBit of the first `U` first
+    /// (the order of `Sha256::evaluate()`).
+    pub fn fix_outputs(&mut self, bits : &[bool]) {
+        assert!(bits.len() <= 32 * self.outputs.len());
+        self.fixed_outputs.extend(bits.iter().cloned().enumerate());
+    }
+
+    /// Fixes input byte `bytenum` to `value`. Input bits that are not `Symbol`s are skipped.
+    pub fn fix_input_byte(&mut self, bytenum : usize, value : u8) {
+        for &(pos, t) in self.inputs.iter().filter(|&&(pos, _)| pos / 8 == bytenum) {
+            self.fixed_inputs.push((t, value >> (7 - pos % 8) & 1 == 1));
+        }
+    }
+
+    /// The `Term` of output bit `i`.
+    fn output_bit(&self, i : usize) -> RTerm {
+        self.outputs[i / 32].bits()[31 - i % 32]
+    }
+
+    /// Writes the problem with one `Bool` constant per `Symbol` (`s<number>`)
+    /// and one defined `Bool` per gate (`t<index>`).
+    pub fn write_bits<W : Write>(&self, out : &mut W) -> io::Result<()> {
+        let roots : Vec<RTerm> = (0..32 * self.outputs.len()).map(|i| self.output_bit(i)).collect();
+        writeln!(out, "(set-logic QF_UF)")?;
+        TermGraph::with(|g| {
+            let needed = g.cone(&roots);
+            let name = |t : RTerm| match g.term_type(t) {
+                TermType::Symbol(s) => format!("s{}", s),
+                TermType::Constant(c) => (if c { "true" } else { "false" }).to_string(),
+                _ => format!("t{}", t.index()),
+            };
+            let mut symbols : BTreeSet<usize> = self.inputs.iter().filter_map(|&(_, t)| match g.term_type(t) {
+                TermType::Symbol(s) => Some(s),
+                _ => None,
+            }).collect();
+            for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+                if let TermType::Symbol(s) = g.term_type(g.id(i)) {
+                    symbols.insert(s);
+                }
+            }
+            for s in symbols.iter() {
+                writeln!(out, "(declare-const s{} Bool)", s)?;
+            }
+            for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+                let body = match g.term_type(g.id(i)) {
+                    TermType::Symbol(_) | TermType::Constant(_) => continue,
+                    TermType::Xor(x, y) => format!("(xor {} {})", name(x), name(y)),
+                    TermType::And(x, y) => format!("(and {} {})", name(x), name(y)),
+                    TermType::Or(x, y) => format!("(or {} {})", name(x), name(y)),
+                    TermType::Not(x) => format!("(not {})", name(x)),
+                };
+                writeln!(out, "(define-fun t{} () Bool {})", i, body)?;
+            }
+            for &(t, v) in self.fixed_inputs.iter() {
+                writeln!(out, "(assert (= {} {}))", name(t), v)?;
+            }
+            for &(i, v) in self.fixed_outputs.iter() {
+                writeln!(out, "(assert (= {} {}))", name(self.output_bit(i)), v)?;
+            }
+            writeln!(out, "(check-sat)")?;
+            write!(out, "(get-value (")?;
+            for (k, s) in symbols.iter().enumerate() {
+                write!(out, "{}s{}", if k == 0 { "" } else { " " }, s)?;
+            }
+            writeln!(out, "))")
+        })
+    }
+
+    /// Writes the problem with one `(_ BitVec 32)` constant per `Input` word
+    /// (`in<n>`, see `WordGraph::input_symbols()`) and one defined bit vector per word-level expression
+    /// (`w<index>`), using `bvadd`, `bvxor`, `bvand`, `bvnot`, `bvlshr` and
+    /// `rotate_right`.
+    ///
+    /// Fails with `InvalidInput` if an output `U` has no word-level provenance
+    /// or an input bit is not part of an `Input` word.
+    pub fn write_words<W : Write>(&self, out : &mut W) -> io::Result<()> {
+        let roots : Vec<WordId> = self.outputs.iter().map(|u| u.word()).collect::<Option<_>>()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Output without word-level provenance."))?;
+        WordGraph::with(|w| {
+            let needed = w.cone(&roots);
+            let ids : Vec<WordId> = needed.iter().enumerate().filter(|&(_, n)| *n).map(|(i, _)| w.id(i)).collect();
+            let input_words : Vec<usize> = ids.iter().filter_map(|id| match w.op(*id) {
+                WordOp::Input(n) => Some(n),
+                _ => None,
+            }).collect();
+            // Input word and bit of each symbol
+            let positions : HashMap<usize, (usize, usize)> = input_words.iter().flat_map(|&n| {
+                w.input_symbols(n).iter().enumerate().map(move |(b, s)| (*s, (n, b)))
+            }).collect();
+            let input_bit = |t : RTerm| -> io::Result<String> {
+                if let TermType::Symbol(s) = t.term_type() {
+                    if let Some((n, b)) = positions.get(&s) {
+                        return Ok(format!("((_ extract {0} {0}) in{1})", b, n));
+                    }
+                }
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "Input bit is not part of an input word."))
+            };
+            let name = |id : WordId| match w.op(id) {
+                WordOp::Input(n) => format!("in{}", n),
+                WordOp::Const(c) => format!("#x{:08x}", c),
+                _ => format!("w{}", id.index()),
+            };
+
+            writeln!(out, "(set-logic QF_BV)")?;
+            for n in input_words.iter() {
+                writeln!(out, "(declare-const in{} (_ BitVec 32))", n)?;
+            }
+            for id in ids.iter() {
+                let body = match w.op(*id) {
+                    WordOp::Input(_) | WordOp::Const(_) => continue,
+                    WordOp::Xor(a, b) => format!("(bvxor {} {})", name(a), name(b)),
+                    WordOp::And(a, b) => format!("(bvand {} {})", name(a), name(b)),
+                    WordOp::Add(a, b) => format!("(bvadd {} {})", name(a), name(b)),
+                    WordOp::Not(a) => format!("(bvnot {})", name(a)),
+                    WordOp::RotateRight(a, x) => format!("((_ rotate_right {}) {})", x, name(a)),
+                    WordOp::ShiftRight(a, x) => format!("(bvlshr {} #x{:08x})", name(a), x),
+                };
+                writeln!(out, "(define-fun w{} () (_ BitVec 32) {})", id.index(), body)?;
+            }
+            for &(t, v) in self.fixed_inputs.iter() {
+                writeln!(out, "(assert (= {} #b{}))", input_bit(t)?, v as u8)?;
+            }
+            for &(i, v) in self.fixed_outputs.iter() {
+                let bit = 31 - i % 32;
+                writeln!(out, "(assert (= ((_ extract {0} {0}) {1}) #b{2}))", bit, name(roots[i / 32]), v as u8)?;
+            }
+            writeln!(out, "(check-sat)")?;
+            write!(out, "(get-value (")?;
+            for (k, n) in input_words.iter().enumerate() {
+                write!(out, "{}in{}", if k == 0 { "" } else { " " }, n)?;
+            }
+            writeln!(out, "))")
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balanced(s : &str) -> bool {
+        let mut depth = 0i64;
+        for c in s.chars() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {},
+            }
+            if depth < 0 {
+                return false;
+            }
+        }
+        depth == 0
+    }
+
+    #[test]
+    fn sha_bits_and_words() {
+        let data = vec![U::new_symbolic()];
+        data[0].set_byte(b'a', 0);
+        data[0].set_byte(b'\n', 1);
+        // Replaced by padding, but still part of the input word.
+        data[0].set_byte(0, 2);
+        data[0].set_byte(0, 3);
+        let s = Sha256::new(data, 16);
+        for u in s.digest.iter() {
+            assert_eq!(u.word().unwrap().eval_to_u32(), u.eval_to_u32());
+        }
+        let mut smt = Smt::sha(&s);
+        smt.fix_digest("87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7");
+        smt.fix_input_byte(0, b'a');
+
+        let mut bits = Vec::new();
+        smt.write_bits(&mut bits).unwrap();
+        let bits = String::from_utf8(bits).unwrap();
+        assert!(balanced(&bits));
+        assert!(bits.starts_with("(set-logic QF_UF)\n"));
+        assert_eq!(bits.matches("(declare-const").count(), 16);
+        assert_eq!(bits.matches("(assert").count(), 256 + 8);
+
+        let mut words = Vec::new();
+        smt.write_words(&mut words).unwrap();
+        let words = String::from_utf8(words).unwrap();
+        assert!(balanced(&words));
+        assert!(words.starts_with("(set-logic QF_BV)\n"));
+        assert_eq!(words.matches("(declare-const").count(), 1);
+        assert_eq!(words.matches("(assert").count(), 256 + 8);
+        assert!(words.contains("bvadd"));
+        assert!(words.contains("(_ rotate_right 7)"));
+        assert!(words.len() * 10 < bits.len());
+        // 'a' = 0b0110_0001, the MSBit of the message is bit 31 of the first word.
+        let input = match WordGraph::with(|w| w.op(s.data[0].word().unwrap())) {
+            WordOp::Xor(_, a) => match a.op() {
+                WordOp::And(i, _) => i.op(),
+                op => panic!("{:?}", op),
+            },
+            op => panic!("{:?}", op),
+        };
+        let n = match input { WordOp::Input(n) => n, op => panic!("{:?}", op) };
+        assert!(words.contains(&format!("(assert (= ((_ extract 31 31) in{}) #b0))", n)));
+        assert!(words.contains(&format!("(assert (= ((_ extract 30 30) in{}) #b1))", n)));
+    }
+
+    #[test]
+    fn missing_provenance() {
+        let u = [U::from_bits(U::new_symbolic().bits())];
+        let smt = Smt::new(&u, &u);
+        assert!(smt.write_words(&mut Vec::new()).is_err());
+        assert!(smt.write_bits(&mut Vec::new()).is_ok());
+    }
+}
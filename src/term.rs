@@ -1,11 +1,12 @@
 //! `term::Term`: A fuzzy bit.
 
 use std::fmt;
-use std::cell::Cell;
-use std::rc::Rc;
+use std::cell::RefCell;
+use std::marker::PhantomData;
 use std::cmp::max;
+use std::collections::{HashMap, BTreeSet};
+use ::semantics::{FuzzySemantics, Standard};
 
-#[derive(Clone)]
 /// A `Term` is either [constant, symbolic or the result of a logical operation
 /// of other terms](enum.TermType.html).
 ///
@@ -20,20 +21,25 @@ use std::cmp::max;
 /// * The `Term` can be `Symbolic`. Its value is a number between 0.0 and 1.0. In contrast to constants, symbols can not be short cut. During later use, you will set all symbols to choosen values and then evaluate the root term.
 /// * The `Term` can be a logical operation. Currently implemented are: `Xor ^`, `And &`, `Or |` and `Not !`. Except for `Not`, all Terms take two operands.
 ///
-/// The implementation uses reference counted terms `RTerm = Rc<Term>` to prevent copying large parts of trees and allow caching during evaluations. This makes the `Term` network structure not a tree, but an acyclic directed graph with multiple roots, I guess.
+/// `Term`s are not allocated one by one. They are nodes of the per thread
+/// [`TermGraph`](struct.TermGraph.html) and are addressed by the small copyable handle
+/// `RTerm = TermId`. Operands always have a smaller id than the terms using them,
+/// so the graph is an acyclic directed graph with multiple roots whose node
+/// vector is already topologically sorted.
 ///
 /// During those lazy evaluations, every Term caches its value. This caching is not done for successive calls to the root term. It is instead important since some sub trees may be used by thousands of other terms. In other words, the flattened out structure may be very large. (Gigabytes if the logic structure of SHA256 is printed into an ascii file.)
 ///
 /// Use `reset()` to clear the cache.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct Term {
     t : TermType,
-    cached_eval : Cell<Option<f64>>,
 }
 
 /// Types of Terms.
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum TermType {
-    Symbol(Cell<Option<f64>>),
+    /// A settable fuzzy bit. The number is the index of the symbol in creation order.
+    Symbol(usize),
     Constant(bool),
     Xor(RTerm, RTerm),
     And(RTerm, RTerm),
@@ -41,16 +47,762 @@ pub enum TermType {
     Not(RTerm),
 }
 
+/// Index of a `Term` within the `TermGraph` of the current thread.
+///
+/// It only has a meaning for the graph of the thread that created it, so it
+/// is neither `Send` nor `Sync`. Use a `FrozenGraph` to evaluate `Term`s on
+/// other threads.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TermId(u32, PhantomData<*const ()>);
+
 /// The always used Handle for any `Term`.
-pub type RTerm = Rc<Term>;
+pub type RTerm = TermId;
+
+/// Arena that owns all `Term`s of a thread.
+///
+/// Each thread has its own graph, which is used implicitly by all the
+/// constructors of `Term` and by the methods of `RTerm`. Use `TermGraph::with()`
+/// to inspect the graph directly.
+///
+/// Nodes are never removed, the graph only grows until its thread ends. So
+/// every `Sha256::new()` on a long-lived thread adds its `Term`s for good,
+/// even if the `Sha256` is dropped. Build many independent circuits on
+/// short-lived threads (e.g. with `std::thread::scope`), each thread starts
+/// with an empty graph.
+///
+/// The evaluation cache and the values of the symbols are stored alongside
+/// the nodes, so a `Term` itself is plain data.
+///
+/// The graph is hash-consed: Creating a logical operation that already exists
+/// (with the same operands, in any order) returns the existing `Term`. Two
+/// structurally identical terms therefore always have the same `RTerm`, so
+/// comparing handles compares whole sub graphs.
+///
+/// On top of constant folding, the logical operations can apply the algebraic
+/// rewrite rules counted in [`Simplifications`](struct.Simplifications.html).
+/// These rules are exact for boolean inputs, but change fuzzy results: e.g.
+/// `a & a` evaluates to `a` instead of `a*a`. So they are off by default, use
+/// `set_simplify(true)` to apply them while building or `simplify()` to apply
+/// them later.
+pub struct TermGraph {
+    nodes : Vec<Term>,
+    cached_eval : Vec<Option<f64>>,
+    symbols : Vec<Option<f64>>,
+    /// The `Term` of each symbol, indexed by symbol number.
+    symbol_terms : Vec<RTerm>,
+    unique : HashMap<TermType, RTerm>,
+    simplify : bool,
+    simplifications : Simplifications,
+    fanout : FanOut,
+}
+
+/// Users of each `Term`, indexed by `RTerm::index()`. Built on demand by
+/// `update()` and extended by the `Term`s created since the last call.
+#[derive(Default)]
+struct FanOut {
+    users : Vec<Vec<RTerm>>,
+    /// Number of `Term`s whose operands have been added.
+    nr_of_terms : usize,
+}
+
+/// Number of `Term`s that each algebraic rewrite rule has eliminated.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Simplifications {
+    /// `a ^ a = 0`
+    pub xor_self : usize,
+    /// `a ^ !a = 1`
+    pub xor_not_self : usize,
+    /// `a & a = a`
+    pub and_self : usize,
+    /// `a & !a = 0`
+    pub and_not_self : usize,
+    /// `a | a = a`
+    pub or_self : usize,
+    /// `a | !a = 1`
+    pub or_not_self : usize,
+    /// `a | (a & b) = a`
+    pub or_absorption : usize,
+    /// `!!a = a`
+    pub double_not : usize,
+}
+
+thread_local!(static GRAPH : RefCell<TermGraph> = RefCell::new(TermGraph::new()));
+
+const FALSE : RTerm = TermId(0, PhantomData);
+const TRUE : RTerm = TermId(1, PhantomData);
+
+impl TermId {
+    /// Position of the `Term` in the node vector of its `TermGraph`.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl TermGraph {
+
+    fn new() -> TermGraph {
+        let mut g = TermGraph {
+            nodes : Vec::new(),
+            cached_eval : Vec::new(),
+            symbols : Vec::new(),
+            symbol_terms : Vec::new(),
+            unique : HashMap::new(),
+            simplify : false,
+            simplifications : Simplifications::default(),
+            fanout : FanOut::default(),
+        };
+        // The two constants always exist and are shared by everyone.
+        g.push(TermType::Constant(false));
+        g.push(TermType::Constant(true));
+        g
+    }
+
+    /// Runs `f` with the `TermGraph` of the current thread.
+    ///
+    /// Do not create or evaluate `Term`s from within `f`, that would panic
+    /// because the graph is already borrowed.
+    pub fn with<F, R>(f : F) -> R where F : FnOnce(&TermGraph) -> R {
+        GRAPH.with(|g| f(&g.borrow()))
+    }
+
+    /// Runs `f` with mutable access to the `TermGraph` of the current thread.
+    pub fn with_mut<F, R>(f : F) -> R where F : FnOnce(&mut TermGraph) -> R {
+        GRAPH.with(|g| f(&mut g.borrow_mut()))
+    }
+
+    /// Number of `Term`s in this graph, including the two constants.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Always false, the two constants are part of every graph.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Number of `Symbol`s created so far.
+    pub fn nr_of_symbols(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Returns the handle of the `Term` at position `index` of the node vector.
+    pub fn id(&self, index : usize) -> RTerm {
+        assert!(index < self.nodes.len());
+        TermId(index as u32, PhantomData)
+    }
+
+    /// Returns the type of the `Term` behind `id`.
+    pub fn term_type(&self, id : RTerm) -> TermType {
+        self.nodes[id.index()].t
+    }
+
+    /// Enables or disables the algebraic rewrite rules during construction
+    /// of new `Term`s. Constant folding is always done. Disabled by default,
+    /// since the rules change the fuzzy evaluation of non-boolean inputs.
+    pub fn set_simplify(&mut self, simplify : bool) {
+        self.simplify = simplify;
+    }
+
+    /// Number of `Term`s the rewrite rules have eliminated so far, during
+    /// construction and during `simplify()`.
+    pub fn simplifications(&self) -> Simplifications {
+        self.simplifications
+    }
+
+    /// Marks all `Term`s that `roots` depend on, including the roots.
+    ///
+    /// The returned vector is indexed by `RTerm::index()` and ends at the
+    /// largest root, since no `Term` depends on a `Term` created after it.
+    pub fn cone(&self, roots : &[RTerm]) -> Vec<bool> {
+        let len = roots.iter().max().map_or(0, |r| r.index() + 1);
+        // Operands always have smaller ids, so one descending sweep finds the
+        // whole cone.
+        let mut needed = vec![false; len];
+        for r in roots {
+            needed[r.index()] = true;
+        }
+        for i in (0..len).rev() {
+            if needed[i] {
+                match self.nodes[i].t {
+                    TermType::Xor(x, y) | TermType::And(x, y) | TermType::Or(x, y) => {
+                        needed[x.index()] = true;
+                        needed[y.index()] = true;
+                    },
+                    TermType::Not(x) => needed[x.index()] = true,
+                    TermType::Symbol(_) | TermType::Constant(_) => {},
+                }
+            }
+        }
+        needed
+    }
+
+    /// Rebuilds all `Term`s that `roots` depend on with the algebraic rewrite
+    /// rules enabled, bottom up, so that a simplification of an operand can
+    /// enable further simplifications of the terms using it.
+    ///
+    /// Returns the new roots (in the same order) and the number of `Term`s
+    /// each rule eliminated during this pass. The old `Term`s stay valid.
+    pub fn simplify(&mut self, roots : &[RTerm]) -> (Vec<RTerm>, Simplifications) {
+        let before = self.simplifications;
+        let simplify = self.simplify;
+        self.simplify = true;
+
+        // One ascending sweep over the cone rebuilds it, operands first.
+        let needed = self.cone(roots);
+        let mut map : Vec<RTerm> = Vec::with_capacity(needed.len());
+        for (i, &needed) in needed.iter().enumerate() {
+            let old = TermId(i as u32, PhantomData);
+            let new = if !needed {
+                old
+            } else {
+                match self.nodes[i].t {
+                    TermType::Symbol(_) | TermType::Constant(_) => old,
+                    TermType::Xor(x, y) => self.xor(map[x.index()], map[y.index()]),
+                    TermType::And(x, y) => self.and(map[x.index()], map[y.index()]),
+                    TermType::Or(x, y) => self.or(map[x.index()], map[y.index()]),
+                    TermType::Not(x) => self.not(map[x.index()]),
+                }
+            };
+            map.push(new);
+        }
+
+        self.simplify = simplify;
+        let new_roots = roots.iter().map(|r| map[r.index()]).collect();
+        (new_roots, self.simplifications.since(&before))
+    }
+
+    /// Rebuilds all `Term`s that `roots` depend on with every `Symbol` that
+    /// has been `set()` replaced by the constant of its value, rounded to 0
+    /// or 1. The constants are folded away, so only the unset symbols remain
+    /// as inputs of the new roots. See also `evaluate_ternary()`.
+    ///
+    /// Returns the new roots, in the same order. The old `Term`s stay valid.
+    pub fn partial_evaluate(&mut self, roots : &[RTerm]) -> Vec<RTerm> {
+        let needed = self.cone(roots);
+        let mut map : Vec<RTerm> = Vec::with_capacity(needed.len());
+        for (i, &needed) in needed.iter().enumerate() {
+            let old = TermId(i as u32, PhantomData);
+            let new = if !needed {
+                old
+            } else {
+                match self.nodes[i].t {
+                    TermType::Symbol(s) => match self.symbols[s] {
+                        Some(v) => TermGraph::constant(v >= 0.5),
+                        None => old,
+                    },
+                    TermType::Constant(_) => old,
+                    TermType::Xor(x, y) => self.xor(map[x.index()], map[y.index()]),
+                    TermType::And(x, y) => self.and(map[x.index()], map[y.index()]),
+                    TermType::Or(x, y) => self.or(map[x.index()], map[y.index()]),
+                    TermType::Not(x) => self.not(map[x.index()]),
+                }
+            };
+            map.push(new);
+        }
+        roots.iter().map(|r| map[r.index()]).collect()
+    }
+
+    /// Incremental re-evaluation after some `Symbol`s have been `set()` to new values.
+    ///
+    /// Only the `Term`s that depend on the `changed` symbols are evaluated again,
+    /// in creation order. If the new value of a `Term` equals its cached value,
+    /// its users are not touched at all. `Term`s without cached value (e.g.
+    /// after `reset()` of a single symbol) are not evaluated, they are
+    /// calculated by the next `evaluate()` as usual, but their users are
+    /// updated as if they had changed.
+    ///
+    /// Use this instead of `reset()` and `evaluate()` if the roots have been
+    /// evaluated with the same `sem` before and only a few symbols changed.
+    ///
+    /// Returns the number of `Term`s that have been evaluated again.
+    pub fn update<S : FuzzySemantics + ?Sized>(&mut self, changed : &[RTerm], sem : &S) -> usize {
+        self.extend_fanout();
+        let mut dirty = BTreeSet::new();
+        for c in changed {
+            assert!(matches!(self.term_type(*c), TermType::Symbol(_)), "Changed term is not a symbol");
+            dirty.insert(*c);
+        }
+        let mut nr_evaluated = 0;
+        while let Some(t) = dirty.pop_first() {
+            // Without a cached value, the users may still have cached values
+            // that depend on the old one, so they are dirty as well.
+            let changed = match self.cached_eval[t.index()] {
+                Some(old) => {
+                    self.cached_eval[t.index()] = None;
+                    nr_evaluated += 1;
+                    self.evaluate(t, sem) != old
+                },
+                None => true,
+            };
+            if changed {
+                dirty.extend(self.fanout.users[t.index()].iter().cloned());
+            }
+        }
+        nr_evaluated
+    }
+
+    /// Adds the users of the `Term`s created since the last call to the fan-out.
+    fn extend_fanout(&mut self) {
+        self.fanout.users.resize(self.nodes.len(), Vec::new());
+        for i in self.fanout.nr_of_terms..self.nodes.len() {
+            let user = TermId(i as u32, PhantomData);
+            match self.nodes[i].t {
+                TermType::Xor(x, y) | TermType::And(x, y) | TermType::Or(x, y) => {
+                    self.fanout.users[x.index()].push(user);
+                    self.fanout.users[y.index()].push(user);
+                },
+                TermType::Not(x) => self.fanout.users[x.index()].push(user),
+                TermType::Symbol(_) | TermType::Constant(_) => {},
+            }
+        }
+        self.fanout.nr_of_terms = self.nodes.len();
+    }
+
+    /// Reverse mode automatic differentiation.
+    ///
+    /// Evaluates `roots` with `sem` (using the eval cache like `evaluate()`)
+    /// and then propagates the derivatives `d_roots` of a scalar loss with
+    /// respect to the roots backwards through the graph, in one sweep over
+    /// the `Term`s in reverse creation order.
+    ///
+    /// Returns the derivative of the loss with respect to each `Symbol`,
+    /// indexed by the number in `TermType::Symbol`. Symbols the roots do not
+    /// depend on get 0.
+    pub fn gradient<S : FuzzySemantics + ?Sized>(&mut self, roots : &[RTerm], d_roots : &[f64], sem : &S) -> Vec<f64> {
+        assert_eq!(roots.len(), d_roots.len());
+        let mut grad = vec![0.; self.symbols.len()];
+        let max_root = match roots.iter().max() {
+            Some(r) => r.index(),
+            None => return grad,
+        };
+        for r in roots {
+            self.evaluate(*r, sem);
+        }
+        let mut adjoint = vec![0.; max_root + 1];
+        for (r, d) in roots.iter().zip(d_roots.iter()) {
+            adjoint[r.index()] += *d;
+        }
+        // Every term that gets a nonzero adjoint is part of the roots' cone
+        // and therefore has been evaluated.
+        let value = |g : &TermGraph, x : RTerm| g.cached_eval[x.index()].expect("Term not evaluated.");
+        for i in (0..max_root + 1).rev() {
+            let d = adjoint[i];
+            if d == 0. {
+                continue;
+            }
+            match self.nodes[i].t {
+                TermType::Symbol(s) => grad[s] += d,
+                TermType::Constant(_) => {},
+                TermType::Xor(x, y) => {
+                    let (dx, dy) = sem.d_xor(value(self, x), value(self, y));
+                    adjoint[x.index()] += d * dx;
+                    adjoint[y.index()] += d * dy;
+                },
+                TermType::And(x, y) => {
+                    let (dx, dy) = sem.d_and(value(self, x), value(self, y));
+                    adjoint[x.index()] += d * dx;
+                    adjoint[y.index()] += d * dy;
+                },
+                TermType::Or(x, y) => {
+                    let (dx, dy) = sem.d_or(value(self, x), value(self, y));
+                    adjoint[x.index()] += d * dx;
+                    adjoint[y.index()] += d * dy;
+                },
+                TermType::Not(x) => {
+                    adjoint[x.index()] += d * sem.d_not(value(self, x));
+                },
+            }
+        }
+        grad
+    }
+
+    /// Returns the value a `Symbol` has been `set()` to, if any.
+    pub fn symbol_value(&self, symbol : usize) -> Option<f64> {
+        self.symbols[symbol]
+    }
+
+    /// Returns the `Term` of symbol number `symbol`.
+    pub fn symbol_term(&self, symbol : usize) -> RTerm {
+        self.symbol_terms[symbol]
+    }
+
+    fn push(&mut self, t : TermType) -> RTerm {
+        assert!(self.nodes.len() < u32::MAX as usize, "TermGraph is full.");
+        let id = TermId(self.nodes.len() as u32, PhantomData);
+        let cached = match t {
+            TermType::Constant(c) => Some(if c { 1. } else { 0. }),
+            _ => None,
+        };
+        self.nodes.push(Term { t });
+        self.cached_eval.push(cached);
+        id
+    }
+
+    /// Returns the existing `Term` of type `t` or pushes a new one.
+    /// Operands of the commutative operations are sorted first.
+    fn gate(&mut self, t : TermType) -> RTerm {
+        let t = match t {
+            TermType::Xor(a, b) if b < a => TermType::Xor(b, a),
+            TermType::And(a, b) if b < a => TermType::And(b, a),
+            TermType::Or(a, b) if b < a => TermType::Or(b, a),
+            t => t,
+        };
+        if let Some(&id) = self.unique.get(&t) {
+            return id;
+        }
+        let id = self.push(t);
+        self.unique.insert(t, id);
+        id
+    }
+
+    fn symbol(&mut self) -> RTerm {
+        let s = self.symbols.len();
+        self.symbols.push(None);
+        let id = self.push(TermType::Symbol(s));
+        self.symbol_terms.push(id);
+        id
+    }
+
+    fn is_const(&self, a : RTerm) -> bool {
+        matches!(self.term_type(a), TermType::Constant(_))
+    }
+
+    fn const_val(&self, a : RTerm) -> bool {
+        if let TermType::Constant(b) = self.term_type(a) {
+            b
+        } else {
+            panic!("Term is not const.");
+        }
+    }
+
+    /// True if `a == !b` or `!a == b`.
+    fn is_not_of(&self, a : RTerm, b : RTerm) -> bool {
+        self.term_type(a) == TermType::Not(b) || self.term_type(b) == TermType::Not(a)
+    }
+
+    /// True if `b` is `a & x` or `x & a`, so that `a | b == a`.
+    fn absorbs(&self, a : RTerm, b : RTerm) -> bool {
+        match self.term_type(b) {
+            TermType::And(x, y) => x == a || y == a,
+            _ => false,
+        }
+    }
+
+    fn constant(c : bool) -> RTerm {
+        if c { TRUE } else { FALSE }
+    }
+
+    fn xor(&mut self, a : RTerm, b : RTerm) -> RTerm {
+        if self.is_const(a) && self.is_const(b) {
+            return TermGraph::constant(self.const_val(a) ^ self.const_val(b));
+        } else if self.is_const(a) && self.const_val(a) {
+            return self.not(b);
+        } else if self.is_const(a) && !self.const_val(a) {
+            return b;
+        } else if self.is_const(b) && self.const_val(b) {
+            return self.not(a);
+        } else if self.is_const(b) && !self.const_val(b) {
+            return a;
+        }
+        // no consts
+        if self.simplify {
+            if a == b {
+                self.simplifications.xor_self += 1;
+                return FALSE;
+            } else if self.is_not_of(a, b) {
+                self.simplifications.xor_not_self += 1;
+                return TRUE;
+            }
+        }
+        self.gate(TermType::Xor(a, b))
+    }
+
+    fn or(&mut self, a : RTerm, b : RTerm) -> RTerm {
+        if self.is_const(a) && self.is_const(b) {
+            return TermGraph::constant(self.const_val(a) | self.const_val(b));
+        } else if self.is_const(a) && self.const_val(a) {
+            return TRUE; // 1 | b => 1
+        } else if self.is_const(a) && !self.const_val(a) {
+            return b;  // 0 | b => b
+        } else if self.is_const(b) && self.const_val(b) {
+            return TRUE; // a | 1 => 1
+        } else if self.is_const(b) && !self.const_val(b) {
+            return a;  // a | 0 => a
+        }
+        // no consts
+        if self.simplify {
+            if a == b {
+                self.simplifications.or_self += 1;
+                return a;
+            } else if self.is_not_of(a, b) {
+                self.simplifications.or_not_self += 1;
+                return TRUE;
+            } else if self.absorbs(a, b) {
+                self.simplifications.or_absorption += 1;
+                return a;
+            } else if self.absorbs(b, a) {
+                self.simplifications.or_absorption += 1;
+                return b;
+            }
+        }
+        self.gate(TermType::Or(a, b))
+    }
+
+    fn and(&mut self, a : RTerm, b : RTerm) -> RTerm {
+        if self.is_const(a) && self.is_const(b) {
+            return TermGraph::constant(self.const_val(a) & self.const_val(b));
+        } else if self.is_const(a) && self.const_val(a) {
+            return b; // 1 & b => b
+        } else if self.is_const(a) && !self.const_val(a) {
+            return FALSE;  // 0 & b => 0
+        } else if self.is_const(b) && self.const_val(b) {
+            return a; // a & 1 => a
+        } else if self.is_const(b) && !self.const_val(b) {
+            return FALSE;  // a & 0 => 0
+        }
+        if self.simplify {
+            if a == b {
+                self.simplifications.and_self += 1;
+                return a;
+            } else if self.is_not_of(a, b) {
+                self.simplifications.and_not_self += 1;
+                return FALSE;
+            }
+        }
+        self.gate(TermType::And(a, b))
+    }
+
+    fn not(&mut self, a : RTerm) -> RTerm {
+        if self.is_const(a) {
+            return TermGraph::constant(!self.const_val(a));
+        }
+        if self.simplify {
+            if let TermType::Not(x) = self.term_type(a) {
+                self.simplifications.double_not += 1;
+                return x;
+            }
+        }
+        self.gate(TermType::Not(a))
+    }
+
+    fn set(&mut self, a : RTerm, n : f64) {
+        if let TermType::Symbol(s) = self.term_type(a) {
+            self.symbols[s] = Some(n);
+        } else {
+            panic!("Called set on non-symbol");
+        }
+    }
+
+    /// Resets the cache of `a` and its operands, but does not descend into
+    /// `Term`s that have already been reset. Uses an explicit stack.
+    fn reset(&mut self, a : RTerm) {
+        let mut stack = vec![a];
+        while let Some(t) = stack.pop() {
+            if self.cached_eval[t.index()].is_none() {
+                continue;
+            }
+            match self.term_type(t) {
+                TermType::Constant(_) => {},
+                TermType::Symbol(_) => {
+                    self.cached_eval[t.index()] = None;
+                },
+                TermType::Xor(x, y) | TermType::And(x, y) | TermType::Or(x, y) => {
+                    self.cached_eval[t.index()] = None;
+                    stack.push(x);
+                    stack.push(y);
+                },
+                TermType::Not(x) => {
+                    self.cached_eval[t.index()] = None;
+                    stack.push(x);
+                },
+            }
+        }
+    }
+
+    /// Cached value of a `Term` whose value has been calculated before.
+    fn cached(&self, a : RTerm) -> f64 {
+        self.cached_eval[a.index()].expect("Term not evaluated.")
+    }
+
+    /// Visits all `Term`s `a` depends on that have no cached value yet, operands
+    /// first, and caches the value `f` calculates for each of them. `f` can
+    /// rely on cached values for all operands.
+    ///
+    /// This replaces recursion by an explicit stack on the heap, so the depth
+    /// of the graph is not limited by the size of the thread's stack.
+    ///
+    /// Returns the maximum size the explicit stack reached.
+    fn post_order<F>(&mut self, a : RTerm, mut f : F) -> usize where F : FnMut(&TermGraph, RTerm) -> f64 {
+        let mut max_stacksize = 0;
+        let mut stack = vec![a];
+        while let Some(&t) = stack.last() {
+            max_stacksize = max(max_stacksize, stack.len());
+            if self.cached_eval[t.index()].is_some() {
+                stack.pop();
+                continue;
+            }
+            let len = stack.len();
+            match self.term_type(t) {
+                TermType::Xor(x, y) | TermType::And(x, y) | TermType::Or(x, y) => {
+                    if self.cached_eval[y.index()].is_none() {
+                        stack.push(y);
+                    }
+                    if self.cached_eval[x.index()].is_none() {
+                        stack.push(x);
+                    }
+                },
+                TermType::Not(x) => {
+                    if self.cached_eval[x.index()].is_none() {
+                        stack.push(x);
+                    }
+                },
+                TermType::Symbol(_) | TermType::Constant(_) => {},
+            }
+            if stack.len() == len {
+                // all operands are cached
+                let v = f(self, t);
+                self.cached_eval[t.index()] = Some(v);
+                stack.pop();
+            }
+        }
+        max_stacksize
+    }
+
+    fn evaluate<S : FuzzySemantics + ?Sized>(&mut self, a : RTerm, sem : &S) -> f64 {
+        self.post_order(a, |g, t| {
+            match g.term_type(t) {
+                TermType::Symbol(s) => {
+                    g.symbols[s].expect("Symbol not set. Eval failed.")
+                },
+                TermType::Constant(c) => {
+                    if c { 1. } else { 0. }
+                },
+                TermType::Xor(x, y) => sem.xor(g.cached(x), g.cached(y)),
+                TermType::And(x, y) => sem.and(g.cached(x), g.cached(y)),
+                TermType::Or(x, y) => sem.or(g.cached(x), g.cached(y)),
+                TermType::Not(x) => sem.not(g.cached(x)),
+            }
+        });
+        self.cached(a)
+    }
+
+    fn max_logic_depth_and_max_stack_size(&mut self, a : RTerm, stack_cnt : usize) -> (usize, usize) {
+        // Misuse eval cache to save max logic depth.
+        // This results in the same cache set behaviour as if
+        // evaluate() has been called -> same stack behaviour.
+        let depth = |g : &TermGraph, x : RTerm| if g.is_const(x) { 1. } else { g.cached(x) };
+        let max_stacksize = self.post_order(a, |g, t| {
+            match g.term_type(t) {
+                TermType::Symbol(_) | TermType::Constant(_) => 1.,
+                TermType::Xor(x, y) | TermType::And(x, y) | TermType::Or(x, y) => {
+                    1. + depth(g, x).max(depth(g, y))
+                },
+                TermType::Not(x) => 1. + depth(g, x),
+            }
+        });
+        (depth(self, a) as usize, stack_cnt + max_stacksize)
+    }
+
+    fn nr_of_terms(&mut self, a : RTerm) -> usize {
+        // misuse eval cache to prevent double counting
+        let mut nr = 0;
+        self.post_order(a, |_, _| {
+            nr += 1;
+            1.
+        });
+        nr
+    }
+
+    fn nr_of_terms_flattened(&mut self, a : RTerm) -> usize {
+        // misuse eval cache to save the flattened size of each sub tree
+        let size = |g : &TermGraph, x : RTerm| if g.is_const(x) { 1. } else { g.cached(x) };
+        self.post_order(a, |g, t| {
+            match g.term_type(t) {
+                TermType::Symbol(_) | TermType::Constant(_) => 1.,
+                TermType::Xor(x, y) | TermType::And(x, y) | TermType::Or(x, y) => {
+                    1. + size(g, x) + size(g, y)
+                },
+                TermType::Not(x) => 1. + size(g, x),
+            }
+        });
+        size(self, a) as usize
+    }
+
+    fn fmt_term(&self, a : RTerm, f : &mut fmt::Formatter) -> fmt::Result {
+        match self.term_type(a) {
+            TermType::Symbol(_) => {
+                write!(f, ":")
+            },
+            TermType::Constant(c) => {
+                write!(f, "{}", if c { 1 } else { 0 })
+            },
+            TermType::Xor(x, y) => {
+                write!(f, "(")?;
+                self.fmt_term(x, f)?;
+                write!(f, " Xor ")?;
+                self.fmt_term(y, f)?;
+                write!(f, ")")
+            },
+            TermType::And(x, y) => {
+                write!(f, "(")?;
+                self.fmt_term(x, f)?;
+                write!(f, " & ")?;
+                self.fmt_term(y, f)?;
+                write!(f, ")")
+            },
+            TermType::Or(x, y) => {
+                self.fmt_term(x, f)?;
+                write!(f, " | ")?;
+                self.fmt_term(y, f)
+            },
+            TermType::Not(x) => {
+                write!(f, "! ")?;
+                self.fmt_term(x, f)
+            },
+        }
+    }
+}
+
+impl Simplifications {
+    /// Sum over all rules.
+    pub fn total(&self) -> usize {
+        self.xor_self + self.xor_not_self + self.and_self + self.and_not_self +
+        self.or_self + self.or_not_self + self.or_absorption + self.double_not
+    }
+
+    fn since(&self, before : &Simplifications) -> Simplifications {
+        Simplifications {
+            xor_self : self.xor_self - before.xor_self,
+            xor_not_self : self.xor_not_self - before.xor_not_self,
+            and_self : self.and_self - before.and_self,
+            and_not_self : self.and_not_self - before.and_not_self,
+            or_self : self.or_self - before.or_self,
+            or_not_self : self.or_not_self - before.or_not_self,
+            or_absorption : self.or_absorption - before.or_absorption,
+            double_not : self.double_not - before.double_not,
+        }
+    }
+}
+
+impl fmt::Display for Simplifications {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a^a=0: {}\na^!a=1: {}\na&a=a: {}\na&!a=0: {}\na|a=a: {}\na|!a=1: {}\na|(a&b)=a: {}\n!!a=a: {}\nTotal: {}",
+            self.xor_self,
+            self.xor_not_self,
+            self.and_self,
+            self.and_not_self,
+            self.or_self,
+            self.or_not_self,
+            self.or_absorption,
+            self.double_not,
+            self.total())
+    }
+}
 
 impl Term {
     /// Create a `Symbol` type term with unassigned value. Assign value with `set()`.
     pub fn symbol() -> RTerm {
-        RTerm::new(Term{
-            t : TermType::Symbol(Cell::new(None)),
-            cached_eval : Cell::new(None),
-        })
+        TermGraph::with_mut(|g| g.symbol())
     }
 
     /// Shortcut for creating a `true` constant.
@@ -63,168 +815,91 @@ impl Term {
         Term::constant(false)
     }
 
-    /// Create a `Constant` type term with given value.
+    /// Returns the `Constant` type term with given value. There is only one
+    /// `Term` for each constant value.
     pub fn constant(c : bool) -> RTerm {
-        RTerm::new(Term{
-            t : TermType::Constant(c),
-            cached_eval : Cell::new(Some( if c { 1. } else { 0. })),
-        })
-    }
-
-    /// Checks if this term is of type constant.
-    pub fn is_const(&self) -> bool {
-        if let TermType::Constant(_) = self.t {
-            true
-        } else {
-            false
-        }
-    }
-
-    /// Returns the constant value of this `Term`.
-    /// Panics if type is not `Constant`.
-    pub fn const_val(&self) -> bool {
-        if let TermType::Constant(b) = self.t {
-            b
-        } else {
-            panic!("Term is not const.");
-        }
+        TermGraph::constant(c)
     }
 
     /// Creates a new `RTerm` that lazily evaluates to the xor operation `a ^ b`
     /// of the two input terms.
-    pub fn xor (a : &RTerm, b : &RTerm) -> RTerm {
-        if a.is_const() && b.is_const() {
-            return Term::constant(a.const_val() ^ b.const_val());
-        } else if a.is_const() && a.const_val() == true {
-            return Term::not(&b);
-        } else if a.is_const() && a.const_val() == false {
-            return b.clone();
-        } else if b.is_const() && b.const_val() == true {
-            return Term::not(&a);
-        } else if b.is_const() && b.const_val() == false {
-            return a.clone();
-        }
-        // no consts
-        RTerm::new(Term{
-            t : TermType::Xor(a.clone(), b.clone()),
-            cached_eval : Cell::new(None),
-        })
+    pub fn xor(a : &RTerm, b : &RTerm) -> RTerm {
+        TermGraph::with_mut(|g| g.xor(*a, *b))
     }
 
     /// Creates a new `RTerm` that lazily evaluates to the or operation `a | b`
     /// of the two input terms.
-    pub fn or (a : &RTerm, b : &RTerm) -> RTerm {
-        if a.is_const() && b.is_const() {
-            return Term::constant(a.const_val() | b.const_val());
-        } else if a.is_const() && a.const_val() == true {
-            return Term::c1(); // 1 | b => 1
-        } else if a.is_const() && a.const_val() == false {
-            return b.clone();  // 0 | b => b
-        } else if b.is_const() && b.const_val() == true {
-            return Term::c1(); // a | 1 => 1
-        } else if b.is_const() && b.const_val() == false {
-            return a.clone();  // a | 0 => a
-        }
-        // no consts
-        RTerm::new(Term{
-            t : TermType::Or(a.clone(), b.clone()),
-            cached_eval : Cell::new(None),
-        })
+    pub fn or(a : &RTerm, b : &RTerm) -> RTerm {
+        TermGraph::with_mut(|g| g.or(*a, *b))
     }
 
     /// Creates a new `RTerm` that lazily evaluates to the and operation `a & b`
     /// of the two input terms.
-    pub fn and (a : &RTerm, b : &RTerm) -> RTerm {
-        if a.is_const() && b.is_const() {
-            return Term::constant(a.const_val() & b.const_val());
-        } else if a.is_const() && a.const_val() == true {
-            return b.clone(); // 1 & b => b
-        } else if a.is_const() && a.const_val() == false {
-            return Term::c0();  // 0 & b => 0
-        } else if b.is_const() && b.const_val() == true {
-            return a.clone(); // a & 1 => a
-        } else if b.is_const() && b.const_val() == false {
-            return Term::c0();  // a & 0 => 0
-        }
-        RTerm::new(Term{
-            t : TermType::And(a.clone(), b.clone()),
-            cached_eval : Cell::new(None),
-        })
+    pub fn and(a : &RTerm, b : &RTerm) -> RTerm {
+        TermGraph::with_mut(|g| g.and(*a, *b))
     }
 
     /// Creates a new `RTerm` that lazily evaluates to the not operation `!a`
     /// of the input term.
-    pub fn not (a : &RTerm) -> RTerm {
-        if a.is_const() {
-            return Term::constant(!a.const_val());
-        }
-        RTerm::new(Term{
-            t : TermType::Not(a.clone()),
-            cached_eval : Cell::new(None),
-        })
+    pub fn not(a : &RTerm) -> RTerm {
+        TermGraph::with_mut(|g| g.not(*a))
     }
 
     /// Shortcut for (  a^b  ,  a&b  ). The first term is the sum bit of a logic
     /// half adder, the second term is the carry bit.
     pub fn half_add(a : &RTerm, b : &RTerm) -> (RTerm, RTerm) {
-        (Term::xor(&a, &b),
-         Term::and(&a, &b))
+        TermGraph::with_mut(|g| (g.xor(*a, *b), g.and(*a, *b)))
     }
 
     /// Shortcut for (  a^b^c  ,  (a&b)^(c&(a^b))  ).
     /// The first term is the sum bit of a logic full adder,
     /// the second term is the carry bit.
     pub fn full_add(a : &RTerm, b : &RTerm, carry : &RTerm) -> (RTerm, RTerm) {
-        let a_xor_b = &Term::xor(&a, &b);
-        // Sum: a xor b xor c
-        (Term::xor(&carry, a_xor_b),
-        // Carry: (a & b) xor ( c and (a xor b) )
-         Term::xor( &Term::and(&a, &b), &Term::and(&carry, &a_xor_b)) )
+        TermGraph::with_mut(|g| {
+            let a_xor_b = g.xor(*a, *b);
+            // Sum: a xor b xor c
+            let sum = g.xor(*carry, a_xor_b);
+            // Carry: (a & b) xor ( c and (a xor b) )
+            let a_and_b = g.and(*a, *b);
+            let c_and_a_xor_b = g.and(*carry, a_xor_b);
+            (sum, g.xor(a_and_b, c_and_a_xor_b))
+        })
+    }
+}
+
+impl TermId {
+
+    /// Returns the type of this `Term`.
+    pub fn term_type(&self) -> TermType {
+        TermGraph::with(|g| g.term_type(*self))
+    }
+
+    /// Checks if this term is of type constant.
+    pub fn is_const(&self) -> bool {
+        TermGraph::with(|g| g.is_const(*self))
+    }
+
+    /// Returns the constant value of this `Term`.
+    /// Panics if type is not `Constant`.
+    pub fn const_val(&self) -> bool {
+        TermGraph::with(|g| g.const_val(*self))
     }
 
     /// Sets the value of a `Symbol` type term.
     /// Panics if the type is not `Symbol`.
     pub fn set(&self, n :f64) {
-        if let TermType::Symbol(ref s) = self.t {
-            s.set(Some(n));
-        } else {
-            panic!("Called set on non-symbol");
-        }
+        TermGraph::with_mut(|g| g.set(*self, n))
     }
 
     /// Reset the cached value of this term and all terms this term depends on.
     /// Does not reset anything if this term has already been reset.
     pub fn reset(&self) {
-        if self.cached_eval.get().is_some() {
-            match self.t {
-                TermType::Symbol(_) => {
-                    self.cached_eval.set(None);
-                },
-                TermType::Constant(_) => {},
-                TermType::Xor(ref a, ref b) => {
-                    self.cached_eval.set(None);
-                    a.reset();
-                    b.reset();
-                },
-                TermType::And(ref a, ref b) => {
-                    self.cached_eval.set(None);
-                    a.reset();
-                    b.reset();
-                },
-                TermType::Or(ref b, ref a) => {
-                    self.cached_eval.set(None);
-                    a.reset();
-                    b.reset();
-                },
-                TermType::Not(ref a) => {
-                    self.cached_eval.set(None);
-                    a.reset();
-                },
-            }
-        }
+        TermGraph::with_mut(|g| g.reset(*self))
     }
 
-    /// Evaluate this term by recursively evaluating all sub terms.
+    /// Evaluate this term by evaluating all sub terms, operands first.
+    /// No recursion is involved, so the depth of the tree is only limited by
+    /// the available memory.
     ///
     /// Cache the evaluated value.
     ///
@@ -236,35 +911,19 @@ impl Term {
     ///
     /// `Not: result = 1-a`
     pub fn evaluate(&self) -> f64 {
-        if let Some(c) = self.cached_eval.get() {
-            return c;
-        } else {
-            let v = match self.t {
-                TermType::Symbol(ref s) => {
-                    s.get().expect("Symbol not set. Eval failed.")
-                },
-                TermType::Constant(c) => {
-                    if c { 1. } else { 0. }
-                },
-                TermType::Xor(ref a, ref b) => {
-                    (a.evaluate() - b.evaluate()).abs()
-                },
-                TermType::And(ref a, ref b) => {
-                    a.evaluate() * b.evaluate()
-                },
-                TermType::Or(ref b, ref a) => {
-                     (a.evaluate().sqrt() + b.evaluate().sqrt()).min(1.)
-                },
-                TermType::Not(ref a) => {
-                    1. - a.evaluate()
-                },
-            };
-            self.cached_eval.set(Some(v));
-            return v;
-        }
+        self.evaluate_with(&Standard)
+    }
+
+    /// Same as `evaluate()`, but combines the fuzzy bits with the
+    /// operations defined by `sem`.
+    ///
+    /// The cache does not know which semantics filled it, so call `reset()`
+    /// before switching to another semantics.
+    pub fn evaluate_with<S : FuzzySemantics + ?Sized>(&self, sem : &S) -> f64 {
+        TermGraph::with_mut(|g| g.evaluate(*self, sem))
     }
 
-    /// Stack size needed when evaluating this `Term` recursively.
+    /// Size of the explicit stack needed when evaluating this `Term`.
     /// The stack_cnt is the current stack size. (Use 0 at the root term).
     /// It also returns the maximum logic depth. This value is created by
     /// utilizing the eval cache. So you must call `reset()` before and after
@@ -272,42 +931,7 @@ impl Term {
     ///
     /// Returns: (max_logic_depth, max_stacksize)
     pub fn max_logic_depth_and_max_stack_size(&self, stack_cnt : usize) -> (usize, usize) {
-        // Misuse eval cache to save max logic depth.
-        // This results in the same cache set behaviour as if
-        // evaluate() has been called -> same stack behaviour.
-        if let Some(d) = self.cached_eval.get() {
-            return (d as usize, stack_cnt);
-        } else {
-            let (max_depth, max_stacksize) = match self.t {
-                TermType::Symbol(_) => {
-                    (0, stack_cnt)
-                },
-                TermType::Constant(_) => {
-                    (0, stack_cnt)
-                },
-                TermType::Xor(ref a, ref b) => {
-                    let (a_max_depth, a_max_stacksize) = a.max_logic_depth_and_max_stack_size(stack_cnt + 1);
-                    let (b_max_depth, b_max_stacksize) = b.max_logic_depth_and_max_stack_size(stack_cnt + 1);
-                    (max(a_max_depth, b_max_depth), max(a_max_stacksize, b_max_stacksize))
-                },
-                TermType::And(ref a, ref b) => {
-                    let (a_max_depth, a_max_stacksize) = a.max_logic_depth_and_max_stack_size(stack_cnt + 1);
-                    let (b_max_depth, b_max_stacksize) = b.max_logic_depth_and_max_stack_size(stack_cnt + 1);
-                    (max(a_max_depth, b_max_depth), max(a_max_stacksize, b_max_stacksize))
-                },
-                TermType::Or(ref b, ref a) => {
-                    let (a_max_depth, a_max_stacksize) = a.max_logic_depth_and_max_stack_size(stack_cnt + 1);
-                    let (b_max_depth, b_max_stacksize) = b.max_logic_depth_and_max_stack_size(stack_cnt + 1);
-                    (max(a_max_depth, b_max_depth), max(a_max_stacksize, b_max_stacksize))
-                },
-                TermType::Not(ref a) => {
-                    a.max_logic_depth_and_max_stack_size(stack_cnt + 1)
-                },
-            };
-            let max_depth = max_depth + 1;
-            self.cached_eval.set(Some(max_depth as f64));
-            return (max_depth, max_stacksize);
-        }
+        TermGraph::with_mut(|g| g.max_logic_depth_and_max_stack_size(*self, stack_cnt))
     }
 
     /// Returns the number of RTerms that contribute to the evaluation of this RTerm.
@@ -318,93 +942,24 @@ impl Term {
     ///
     /// Returns: number_of_uncounted_rterms_including_this_rterm
     pub fn nr_of_terms(&self) -> usize {
-        // misuse eval cache to prevent double counting
-        if self.cached_eval.get().is_some() {
-            return 0;
-        } else {
-            let nr = 1 + match self.t {
-                TermType::Symbol(_) => {
-                    0
-                },
-                TermType::Constant(_) => {
-                    0
-                },
-                TermType::Xor(ref a, ref b) => {
-                    a.nr_of_terms() + b.nr_of_terms()
-                },
-                TermType::And(ref a, ref b) => {
-                    a.nr_of_terms() + b.nr_of_terms()
-                },
-                TermType::Or(ref b, ref a) => {
-                    a.nr_of_terms() + b.nr_of_terms()
-                },
-                TermType::Not(ref a) => {
-                    a.nr_of_terms()
-                },
-            };
-            self.cached_eval.set(Some(nr as f64));
-            return nr;
-        }
+        TermGraph::with_mut(|g| g.nr_of_terms(*self))
     }
 
     /// Returns the number of Terms that contribute to the evaluation if the
-    /// tree would have been flattened.
+    /// tree would have been flattened. Saturates at `usize::MAX`.
+    /// This function uses the eval cache, so you must call `reset()` before and
+    /// after using this function.
     pub fn nr_of_terms_flattened(&self) -> usize {
-        // misuse eval cache to prevent double counting
-        if self.cached_eval.get().is_some() {
-            return self.cached_eval.get().unwrap() as usize;
-        } else {
-            let nr = 1 + match self.t {
-                TermType::Symbol(_) => {
-                    0
-                },
-                TermType::Constant(_) => {
-                    0
-                },
-                TermType::Xor(ref a, ref b) => {
-                    a.nr_of_terms() + b.nr_of_terms()
-                },
-                TermType::And(ref a, ref b) => {
-                    a.nr_of_terms() + b.nr_of_terms()
-                },
-                TermType::Or(ref b, ref a) => {
-                    a.nr_of_terms() + b.nr_of_terms()
-                },
-                TermType::Not(ref a) => {
-                    a.nr_of_terms()
-                },
-            };
-            self.cached_eval.set(Some(nr as f64));
-            return nr;
-        }
+        TermGraph::with_mut(|g| g.nr_of_terms_flattened(*self))
     }
 
 }
 
 
 
-impl fmt::Debug for Term {
+impl fmt::Debug for TermId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.t {
-            TermType::Symbol(_) => {
-                write!(f, ":")
-            },
-            TermType::Constant(c) => {
-                write!(f, "{}", if c { 1 } else { 0 })
-            },
-            TermType::Xor(ref a, ref b) => {
-                write!(f, "({:?} Xor {:?})", &a, &b)
-            },
-            TermType::And(ref a, ref b) => {
-                write!(f, "({:?} & {:?})", &a, &b)
-            },
-            TermType::Or(ref a, ref b) => {
-                write!(f, "{:?} | {:?}", &a, &b)
-            },
-            TermType::Not(ref a) => {
-                write!(f, "! {:?}", &a)
-            },
-        }
+        TermGraph::with(|g| g.fmt_term(*self, f))
     }
 }
 
@@ -493,6 +1048,165 @@ mod tests {
         assert_eq!(carry.evaluate(), 1.);
     }
 
+    #[test]
+    fn symbols_share_graph() {
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let x = Term::xor(&a, &b);
+        a.set(1.);
+        b.set(0.25);
+        assert_eq!(x.evaluate(), 0.75);
+        assert_eq!(Term::xor(&x, &Term::c0()), x);
+        assert_eq!(Term::c1(), Term::constant(true));
+        assert!(x.index() > a.index() && x.index() > b.index());
+        assert_eq!(Term::xor(&b, &a), x);
+        x.reset();
+        b.set(1.);
+        assert_eq!(x.evaluate(), 0.);
+    }
 
+    #[test]
+    fn rewrite_rules() {
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let not_a = Term::not(&a);
+        // Off by default, the fuzzy value of a & a stays a*a.
+        let a_and_a = Term::and(&a, &a);
+        assert_ne!(a_and_a, a);
+        a.set(0.5);
+        assert_eq!(a_and_a.evaluate(), 0.25);
+        TermGraph::with_mut(|g| g.set_simplify(true));
+        let a_and_b = Term::and(&a, &b);
+        let before = TermGraph::with(|g| g.simplifications());
+        assert_eq!(Term::xor(&a, &a), Term::c0());
+        assert_eq!(Term::xor(&not_a, &a), Term::c1());
+        assert_eq!(Term::and(&a, &a), a);
+        assert_eq!(Term::and(&a, &not_a), Term::c0());
+        assert_eq!(Term::or(&a, &a), a);
+        assert_eq!(Term::or(&not_a, &a), Term::c1());
+        assert_eq!(Term::or(&a, &a_and_b), a);
+        assert_eq!(Term::or(&a_and_b, &a), a);
+        assert_eq!(Term::not(&not_a), a);
+        let after = TermGraph::with(|g| g.simplifications());
+        let delta = after.since(&before);
+        assert_eq!(delta.total(), 9);
+        assert_eq!(delta.or_absorption, 2);
+        TermGraph::with_mut(|g| g.set_simplify(false));
+    }
+
+    #[test]
+    fn simplify_pass() {
+        let a = Term::symbol();
+        let b = Term::symbol();
+        // ((a & a) ^ !!b) | ((a & a) & b)
+        let a_and_a = Term::and(&a, &a);
+        let not_not_b = Term::not(&Term::not(&b));
+        let root = Term::or(&Term::xor(&a_and_a, &not_not_b), &Term::and(&a_and_a, &b));
+
+        let (new_roots, s) = TermGraph::with_mut(|g| g.simplify(&[root, b]));
+        assert_eq!(new_roots[1], b);
+        assert_eq!(s.and_self, 1);
+        assert_eq!(s.double_not, 1);
+        assert_eq!(s.total(), 2);
+        assert_eq!(new_roots[0], Term::or(&Term::xor(&a, &b), &Term::and(&a, &b)));
+        for &(x, y) in [(0., 0.), (0., 1.), (1., 0.), (1., 1.)].iter() {
+            a.set(x);
+            b.set(y);
+            root.reset();
+            new_roots[0].reset();
+            assert_eq!(root.evaluate(), new_roots[0].evaluate());
+        }
+    }
+
+    #[test]
+    fn gradient() {
+        use ::semantics::Probabilistic;
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let c = Term::symbol();
+        let a_xor_b = Term::xor(&a, &b);
+        // Shared sub term a^b, so the adjoints of two paths have to be summed.
+        let (s, carry) = Term::full_add(&a, &b, &c);
+        let roots = [s, carry, a_xor_b];
+        let d_roots = [1., -2., 0.5];
+        let values = [0.2, 0.7, 0.4];
+        let loss = |values : &[f64]| {
+            for (t, v) in [a, b, c].iter().zip(values.iter()) {
+                t.set(*v);
+            }
+            roots.iter().zip(d_roots.iter()).map(|(r, d)| {
+                r.reset();
+                r.evaluate_with(&Probabilistic) * d
+            }).sum::<f64>()
+        };
+        loss(&values);
+        let grad = TermGraph::with_mut(|g| g.gradient(&roots, &d_roots, &Probabilistic));
+        for (i, t) in [a, b, c].iter().enumerate() {
+            let symbol = match t.term_type() { TermType::Symbol(s) => s, _ => unreachable!() };
+            let mut shifted = values;
+            shifted[i] += 1e-6;
+            let numeric = (loss(&shifted) - loss(&values)) / 1e-6;
+            assert!((grad[symbol] - numeric).abs() < 1e-4, "{} {}", grad[symbol], numeric);
+        }
+    }
+
+    #[test]
+    fn update() {
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let c = Term::symbol();
+        let a_and_b = Term::and(&a, &b);
+        let root1 = Term::xor(&a_and_b, &c);
+        let root2 = Term::or(&Term::not(&c), &b);
+        a.set(0.);
+        b.set(0.5);
+        c.set(0.25);
+        root1.evaluate();
+        root2.evaluate();
+
+        // a & b stays 0, so a change of b only reaches root2.
+        b.set(0.75);
+        let nr = TermGraph::with_mut(|g| g.update(&[b], &Standard));
+        assert_eq!(nr, 3); // b, a & b, root2
+        let (v1, v2) = (root1.evaluate(), root2.evaluate());
+        root1.reset();
+        root2.reset();
+        assert_eq!((v1, v2), (root1.evaluate(), root2.evaluate()));
+
+        a.set(1.);
+        c.set(0.5);
+        TermGraph::with_mut(|g| g.update(&[a, c], &Standard));
+        let (v1, v2) = (root1.evaluate(), root2.evaluate());
+        root1.reset();
+        root2.reset();
+        assert_eq!((v1, v2), (root1.evaluate(), root2.evaluate()));
+
+        // The cache of a & b is cleared, root1 must be updated nevertheless.
+        // root3 is created after the fan-out has been built.
+        let root3 = Term::not(&a_and_b);
+        root3.evaluate();
+        a_and_b.reset();
+        b.set(0.25);
+        TermGraph::with_mut(|g| g.update(&[b], &Standard));
+        let (v1, v3) = (root1.evaluate(), root3.evaluate());
+        root1.reset();
+        root3.reset();
+        assert_eq!((v1, v3), (root1.evaluate(), root3.evaluate()));
+        assert_eq!(v1, 0.25); // |1 * 0.25 - 0.5|
+    }
+
+    #[test]
+    fn hash_consing() {
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let before = TermGraph::with(|g| g.len());
+        let (s1, c1) = Term::full_add(&a, &b, &Term::not(&a));
+        let after = TermGraph::with(|g| g.len());
+        let (s2, c2) = Term::full_add(&b, &a, &Term::not(&a));
+        assert_eq!((s1, c1), (s2, c2));
+        assert_eq!(TermGraph::with(|g| g.len()), after);
+        assert!(after > before);
+        assert_ne!(Term::and(&a, &b), Term::or(&a, &b));
+    }
 
 }
@@ -0,0 +1,101 @@
+//! `ternary`: Three-valued (0/1/X) simulation of `Term`s.
+
+use ::term::{RTerm, TermType, TermGraph};
+
+impl TermGraph {
+    /// Evaluates `roots` with three-valued logic: `Some(bit)` if the value is
+    /// known, `None` (X) if it depends on the unset `Symbol`s. Set symbols
+    /// are rounded to 0 or 1.
+    ///
+    /// A gate is known if its operands are, or if one known operand decides
+    /// it on its own, like `0 & X = 0` or `1 | X = 1`. Since X is taken as
+    /// independent of everything else, `a ^ a` with unset `a` is X. For a
+    /// circuit with the known values folded in, see `partial_evaluate()`.
+    ///
+    /// This does not use or modify the eval cache.
+    pub fn evaluate_ternary(&self, roots : &[RTerm]) -> Vec<Option<bool>> {
+        let needed = self.cone(roots);
+        let mut values : Vec<Option<bool>> = vec![None; needed.len()];
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            let v = |x : RTerm| values[x.index()];
+            values[i] = match self.term_type(self.id(i)) {
+                TermType::Symbol(s) => self.symbol_value(s).map(|v| v >= 0.5),
+                TermType::Constant(c) => Some(c),
+                TermType::Xor(x, y) => match (v(x), v(y)) {
+                    (Some(a), Some(b)) => Some(a ^ b),
+                    _ => None,
+                },
+                TermType::And(x, y) => match (v(x), v(y)) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                },
+                TermType::Or(x, y) => match (v(x), v(y)) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                },
+                TermType::Not(x) => v(x).map(|a| !a),
+            };
+        }
+        roots.iter().map(|r| values[r.index()]).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::term::Term;
+    use ::u::U;
+    use ::sha::Sha256;
+
+    #[test]
+    fn gates() {
+        let a = Term::symbol();
+        let b = Term::symbol();
+        let c = Term::symbol();
+        let roots = [Term::and(&a, &b), Term::or(&a, &b), Term::xor(&a, &c), Term::not(&b)];
+        let ternary = || TermGraph::with(|g| g.evaluate_ternary(&roots));
+        assert_eq!(ternary(), vec![None; 4]);
+        a.set(0.);
+        assert_eq!(ternary(), vec![Some(false), None, None, None]);
+        b.set(0.9);
+        assert_eq!(ternary(), vec![Some(false), Some(true), None, Some(false)]);
+
+        // The partial evaluation folds the known values, `c` stays free.
+        let partial = TermGraph::with_mut(|g| g.partial_evaluate(&roots));
+        assert_eq!(partial, vec![Term::c0(), Term::c1(), c, Term::c0()]);
+        assert_eq!(TermGraph::with(|g| g.evaluate_ternary(&partial)), ternary());
+    }
+
+    #[test]
+    fn message_prefix() {
+        let data : Vec<U> = (0..12).map(|_| U::new_symbolic()).collect();
+        for u in data[..11].iter() {
+            u.set_bytes(b"abcd");
+        }
+        data[11].set_byte(b'e', 0);
+        data[11].set_byte(b'f', 1);
+        data[11].set_byte(b'g', 2);
+        // The last byte is unknown, it enters in round 11.
+        let s = Sha256::with_rounds(data, 32, 16);
+        let ternary = s.evaluate_ternary();
+        assert_eq!(ternary.len(), 256);
+        assert!(ternary.iter().any(|t| t.is_none()));
+
+        let p = s.partial_evaluate();
+        let (before, after) = (s.nr_of_terms(), p.nr_of_terms());
+        println!("Terms: {} -> {}", before, after);
+        assert!(after * 2 < before);
+        for byte in [0u8, b'h', 0xff].iter() {
+            s.data[11].set_byte(*byte, 3);
+            assert_eq!(p.evaluate(), s.evaluate());
+            for (t, v) in ternary.iter().zip(s.evaluate().iter()) {
+                if let Some(t) = t {
+                    assert_eq!(*t, *v == 1.);
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,11 @@
 //! `u::U`: A fuzzy 32bit integer
 
-use ::term::{RTerm, Term};
+use ::term::{RTerm, Term, TermType, TermGraph, Simplifications};
+use ::semantics::{FuzzySemantics, Standard};
+use ::dual::Dual;
+use ::frozen::FrozenGraph;
+use ::dependency::Bitmap;
+use ::word::{WordGraph, WordId, WordOp};
 use std::convert::From;
 use std::fmt;
 use arrayvec::ArrayVec;
@@ -27,52 +32,122 @@ use std::cmp::max;
 ///
 /// It can also be constructed by using operations like `>>`.
 ///
-/// After construction, the bits can be manipulated using the `bits` field
-/// of this struct.
-#[derive(Clone)]
+/// After construction, the bits can be read with `bits()`. They can not be
+/// modified in place, since the word-level provenance (`word()`) describes the
+/// same value. Use `from_bits()` for a `U` of arbitrary bits.
+///
+/// Since the `TermGraph` is hash-consed, two `U`s compare equal if and only if
+/// their bits are built from the same operations on the same symbols. The
+/// word-level provenance is not compared.
+#[derive(Clone, Eq)]
 pub struct U {
     /// The LSBit is bits[0], the MSBit is bits[31]. The byte order is big endian.
     /// Push LSB first.
-    pub bits : ArrayVec<[RTerm ; 32]>,
+    bits : ArrayVec<[RTerm ; 32]>,
+    /// The word-level operation that produced this `U`, if known. It describes
+    /// the same value as `bits`.
+    word : Option<WordId>,
+}
+
+/// Records `op` in the `WordGraph`, if there is an operation (i.e. all operands have a word).
+fn record(op : Option<WordOp>) -> Option<WordId> {
+    op.map(|op| WordGraph::with_mut(|w| w.node(op)))
 }
 
 impl U {
 
     /// Create a new `U` with 32 `Term`s of type `Symbol`. Do not forget to
     /// set the value of each symbol afterwards. You can set the value of each
-    /// bit by using `my_u.bits[22].set(0.3f64)`. Or set 8 bits of this `U` with
+    /// bit by using `my_u.bits()[22].set(0.3f64)`. Or set 8 bits of this `U` with
     /// `my_u.set_byte('e', 2)`.
     pub fn new_symbolic() -> U {
-        let mut u = U { bits : ArrayVec::new() };
-        for _ in 0..32 {
-            u.bits.push(Term::symbol());
+        let mut u = U { bits : ArrayVec::new(), word : None };
+        let mut symbols = [0 ; 32];
+        for s in symbols.iter_mut() {
+            let b = Term::symbol();
+            if let TermType::Symbol(number) = b.term_type() {
+                *s = number;
+            }
+            u.bits.push(b);
         }
+        u.word = Some(WordGraph::with_mut(|w| w.input(symbols)));
         u
     }
 
+    /// Create a new `U` from 32 arbitrary `Term`s, LSBit first. It has no
+    /// word-level provenance.
+    pub fn from_bits(bits : &[RTerm]) -> U {
+        assert_eq!(bits.len(), 32);
+        U { bits : bits.iter().cloned().collect(), word : None }
+    }
+
+    /// The `U` of the `Input` word `id`, made of the `Symbol`s it has been
+    /// created with. Panics if `id` is not an `Input`.
+    pub fn from_input(id : WordId) -> U {
+        let symbols = match id.op() {
+            WordOp::Input(n) => WordGraph::with(|w| *w.input_symbols(n)),
+            op => panic!("Not an input: {:?}", op),
+        };
+        let bits = TermGraph::with(|g| symbols.iter().map(|s| g.symbol_term(*s)).collect());
+        U { bits, word : Some(id) }
+    }
+
     /// Create a new `U` with 32 `Term`s of type `Constant`.
     pub fn from_const(mut c : u32) -> U {
-        let mut u = U { bits : ArrayVec::new() };
+        let mut u = U { bits : ArrayVec::new(), word : record(Some(WordOp::Const(c))) };
         for _ in 0..32 {
             u.bits.push(Term::constant(c & 1u32 == 1u32));
-            c = c >> 1; // c is little endian, but >> delivers the bits from LSBit to MSBit.
+            c >>= 1; // c is little endian, but >> delivers the bits from LSBit to MSBit.
         }
         u
     }
 
+    /// The 32 bits/`Term`s, LSBit first.
+    pub fn bits(&self) -> &[RTerm] {
+        &self.bits
+    }
+
+    /// The word-level operation that produced this `U`, if known.
+    /// See `WordGraph`.
+    pub fn word(&self) -> Option<WordId> {
+        self.word
+    }
+
+    /// Applies the algebraic rewrite rules of the `TermGraph` to the bits of
+    /// all `us` at once and replaces the bits with the simplified `Term`s.
+    /// The rules are exact for boolean inputs, so the word-level provenance
+    /// stays valid.
+    ///
+    /// Returns how many `Term`s each rule eliminated.
+    pub fn simplify(us : &mut [U]) -> Simplifications {
+        let roots : Vec<RTerm> = us.iter().flat_map(|u| u.bits.iter().cloned()).collect();
+        let (new_roots, simplifications) = TermGraph::with_mut(|g| g.simplify(&roots));
+        for (u, bits) in us.iter_mut().zip(new_roots.chunks(32)) {
+            u.bits = bits.iter().cloned().collect();
+        }
+        simplifications
+    }
+
     /// Sets the bits of one byte of this u32 to their min/max values 1.0 and 0.0 depending on `set_value`.
     /// `bytenum` must be one of 0, 1, 2, 3.
     /// Byte 0 is the most significant byte of the four byte representation of this u32.
     ///
     /// Panics if the relevant bits/`Term`s of this `U` are not of type `Symbol`.
     pub fn set_byte(&self,mut set_value : u8, bytenum : usize) {
+        for b in self.byte_bits(bytenum) {
+            b.set((set_value & 1u8) as f64);
+            set_value >>= 1;
+        }
+    }
+
+    /// The 8 bits/`Term`s of one byte of this u32, LSBit first.
+    /// `bytenum` must be one of 0, 1, 2, 3. Byte 0 is the most significant byte,
+    /// like in `set_byte()`.
+    pub fn byte_bits(&self, bytenum : usize) -> &[RTerm] {
         assert!(bytenum <= 3);
         // LSByte 3: bits[8..0]
         // MSByte 0: bits[32..24]
-        for b in self.bits.iter().skip((3-bytenum)*8).take(8) {
-            b.set((set_value & 1u8) as u8 as f64);
-            set_value = set_value >> 1;
-        }
+        &self.bits[(3-bytenum)*8..(4-bytenum)*8]
     }
 
     /// Sets all bits of this `U` to a value dependend on `bytes`. See `set_byte()` for details.
@@ -100,27 +175,52 @@ impl U {
 
     /// Evaluates all bits to a f64 value. Push these values to `out`.
     /// Pushes the MSBit first.
-    pub fn evaluate<'a>(&self, out : &mut Vec<f64>) {
+    pub fn evaluate(&self, out : &mut Vec<f64>) {
+        self.evaluate_with(out, &Standard)
+    }
+
+    /// Same as `evaluate()`, but with the given `FuzzySemantics`.
+    pub fn evaluate_with<S : FuzzySemantics + ?Sized>(&self, out : &mut Vec<f64>, sem : &S) {
         for b in self.bits.iter().rev() {
-            out.push(b.evaluate());
+            out.push(b.evaluate_with(sem));
         }
     }
 
+    /// Evaluates all bits to a `Dual` holding the value and the derivative
+    /// along `direction`. Push these values to `out`. Pushes the MSBit first.
+    /// See `TermGraph::evaluate_dual()`.
+    pub fn evaluate_dual_with<S : FuzzySemantics + ?Sized>(&self, out : &mut Vec<Dual>, direction : &[(RTerm, f64)], sem : &S) {
+        let roots : Vec<RTerm> = self.bits.iter().rev().cloned().collect();
+        out.extend(TermGraph::with(|g| g.evaluate_dual(&roots, direction, sem)));
+    }
+
+    /// Copies the `Term`s of all bits into a `FrozenGraph` that can be
+    /// evaluated on other threads. Its roots are the bits, MSBit first.
+    pub fn freeze(&self) -> FrozenGraph {
+        let roots : Vec<RTerm> = self.bits.iter().rev().cloned().collect();
+        TermGraph::with(|g| g.freeze(&roots))
+    }
+
     /// Evaluate all bits to a `f64` value, then round that value to 0 or 1
     /// and assemble a `u32` with these bits.
     pub fn eval_to_u32(&self) -> u32 {
+        self.eval_to_u32_with(&Standard)
+    }
+
+    /// Same as `eval_to_u32()`, but with the given `FuzzySemantics`.
+    pub fn eval_to_u32_with<S : FuzzySemantics + ?Sized>(&self, sem : &S) -> u32 {
         let mut out = 0;
-        for b in self.bits.iter().rev().map(|b| b.evaluate() >= 0.5) {
-            out = out << 1; // << is independend of the little endian nature of out.
+        for b in self.bits.iter().rev().map(|b| b.evaluate_with(sem) >= 0.5) {
+            out <<= 1; // << is independend of the little endian nature of out.
             if b {
-                out = out | 1u32;
+                out |= 1u32;
             }
         }
         out
     }
 
 
-    /// Max stack size needed when evaluating each bit of this `U` recursively.
+    /// Max size of the explicit stack needed when evaluating each bit of this `U`.
     ///
     /// It also returns the maximum logic depth.
     ///
@@ -148,23 +248,30 @@ impl U {
     ///
     /// Note: manually call reset() before using this function.
     pub fn nr_of_terms_flattened(&self) -> usize {
-        self.bits.iter().map(|u| u.nr_of_terms_flattened()).sum()
+        self.bits.iter().map(|u| u.nr_of_terms_flattened()).fold(0, usize::saturating_add)
+    }
+
+    /// The `inputs` (by index into `inputs`) each bit depends on, LSB first.
+    /// See `TermGraph::dependencies()`.
+    pub fn dependencies(&self, inputs : &[RTerm]) -> Vec<Bitmap> {
+        TermGraph::with(|g| g.dependencies(&self.bits, inputs))
     }
 
     /// Returns a new `U` that evaluates to `self`s value, but bitrotated by `x`
     /// to the right. Rotation happens without any carry bit.
     /// `x` must be less or equal 32.
     pub fn rotate_right(&self, x : usize) -> U {
-        let mut u = U { bits : ArrayVec::new() };
+        let mut u = U { bits : ArrayVec::new(), word : None };
         // Old   :     1000 0000 1100 0000 1010 0000 1001 0011
         // >>3   : 011 1000 0000 1100 0000 1010 0000 1001 0
         assert!(x <= 32);
         for b in self.bits.iter().skip(x) {
-            u.bits.push(b.clone());
+            u.bits.push(*b);
         }
         for b in self.bits.iter().take(x) {
-            u.bits.push(b.clone());
+            u.bits.push(*b);
         }
+        u.word = record(self.word.map(|a| WordOp::RotateRight(a, x)));
         u
     }
 
@@ -172,56 +279,61 @@ impl U {
     /// to the right.
     /// `x` must be less than 32.
     pub fn shift_right(&self, x : usize) -> U {
-        let mut u = U { bits : ArrayVec::new() };
+        let mut u = U { bits : ArrayVec::new(), word : None };
         // Old   :     1000 0000 1100 0000 1010 0000 1001 0011
         // >>3   : 000 1000 0000 1100 0000 1010 0000 1001 0
         assert!(x <= 31);
         for b in self.bits.iter().skip(x) {
-            u.bits.push(b.clone());
+            u.bits.push(*b);
         }
         for _ in 0..x {
             u.bits.push(Term::constant(false));
         }
+        u.word = record(self.word.map(|a| WordOp::ShiftRight(a, x)));
         u
     }
 
     /// Returns a new `U` that evaluates to the bitwise xor with `rhs`.
     pub fn xor(&self, rhs : &U) -> U {
-        let mut u = U { bits : ArrayVec::new() };
+        let mut u = U { bits : ArrayVec::new(), word : None };
         for (b1, b2) in self.bits.iter().zip(rhs.bits.iter()) {
             u.bits.push(Term::xor(b1, b2));
         }
+        u.word = record(self.word.zip(rhs.word).map(|(a, b)| WordOp::Xor(a, b)));
         u
     }
 
     /// Returns a new `U` that evaluates to the bitwise and with `rhs`.
     pub fn and(&self, rhs : &U) -> U {
-        let mut u = U { bits : ArrayVec::new() };
+        let mut u = U { bits : ArrayVec::new(), word : None };
         for (b1, b2) in self.bits.iter().zip(rhs.bits.iter()) {
             u.bits.push(Term::and(b1, b2));
         }
+        u.word = record(self.word.zip(rhs.word).map(|(a, b)| WordOp::And(a, b)));
         u
     }
 
     /// Returns a new `U` that evaluates to the bitwise not with `rhs`.
     pub fn not(&self) -> U {
-        let mut u = U { bits : ArrayVec::new() };
+        let mut u = U { bits : ArrayVec::new(), word : None };
         for b in self.bits.iter() {
             u.bits.push(Term::not(b));
         }
+        u.word = record(self.word.map(WordOp::Not));
         u
     }
 
     /// Returns a new `U` that evaluates to the arithmethic addition with `rhs`.
     pub fn add(&self, rhs : &U) -> U {
-        let mut u = U { bits : ArrayVec::new() };
+        let mut u = U { bits : ArrayVec::new(), word : None };
         let (s, mut c) = Term::half_add(&self.bits[0], &rhs.bits[0]);
         u.bits.push(s);
         for (b1, b2) in self.bits.iter().zip(rhs.bits.iter()).skip(1) {
-            let (s2, c2) = Term::full_add(&b1, &b2, &c);
+            let (s2, c2) = Term::full_add(b1, b2, &c);
             u.bits.push(s2);
             c = c2;
         }
+        u.word = record(self.word.zip(rhs.word).map(|(a, b)| WordOp::Add(a, b)));
         u
     }
 
@@ -233,12 +345,18 @@ impl From<u32> for U {
     }
 }
 
+impl PartialEq for U {
+    fn eq(&self, other : &U) -> bool {
+        self.bits == other.bits
+    }
+}
+
 impl fmt::Debug for U {
     /// Debug: MSB...LSB
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        try!(write!(f, "U["));
+        write!(f, "U[")?;
         for b in self.bits.iter().rev() {
-            try!(b.fmt(f));
+            b.fmt(f)?;
         }
         write!(f, "]")
     }
@@ -339,6 +457,16 @@ mod tests {
     }
 
     #[test]
+    fn equality_ignores_provenance() {
+        let u = U::new_symbolic();
+        let v = U::from_bits(u.bits());
+        assert!(v.word().is_none());
+        assert!(u == v);
+        assert!(u != U::new_symbolic());
+    }
+
+    #[test]
+    #[allow(clippy::identity_op)]
     fn shift_right() {
         let a = 1231414u32;
         let u1 : U = a.into();
@@ -393,7 +521,7 @@ mod tests {
     fn not() {
         let a1 = 11241257u32;
         let u1 : U = a1.into();
-        assert_eq!(!a1, a1 ^ u32::max_value());
+        assert_eq!(!a1, a1 ^ u32::MAX);
         assert_eq!(!a1, !u1.eval_to_u32());
     }
 
@@ -48,9 +48,9 @@ pub fn f64bits_to_u32(i : &[f64]) -> ArrayVec<[u32; 8]> {
     for i_chunk in i.chunks(32) {
         let mut o = 0;
         for b in i_chunk {
-            o = o << 1; // note: first shift does nothing, intended
+            o <<= 1; // note: first shift does nothing, intended
             if *b >= 0.5 {
-                o = o | 1u32;
+                o |= 1u32;
             }
         }
         out.push(o);
@@ -69,7 +69,7 @@ pub fn u32_to_f64bits(hex : &[u32]) -> Vec<f64> {
         let mut c : u32 = *i;
         for _ in 0..32 {
             out.push( if c & 0b10000000_00000000_00000000_00000000_u32 != 0u32 { 1.0 } else { 0.0 } );
-            c = c << 1;
+            c <<= 1;
         }
     }
     out
@@ -90,11 +90,11 @@ mod tests {
     #[test]
     fn f64conversion() {
         let almost1 = (0..256).map(|_| 0.9f64).collect::<Vec<_>>();
-        let ffff : Vec<u32> = (0..8).map(|_| u32::max_value()).collect();
+        let ffff : Vec<u32> = (0..8).map(|_| u32::MAX).collect();
         assert_eq!(f64bits_to_u32(almost1.as_ref()).as_ref(), &ffff[..]);
 
         let almost0 = (0..256).map(|_| 0.1f64).collect::<Vec<_>>();
-        let zzzz : Vec<u32> = (0..8).map(|_| u32::min_value()).collect();
+        let zzzz : Vec<u32> = (0..8).map(|_| u32::MIN).collect();
         assert_eq!(f64bits_to_u32(almost0.as_ref()).as_ref(), &zzzz[..]);
 
         let arbitrary : Vec<u32> = (0..8).map(|i| i*3).collect();
@@ -0,0 +1,338 @@
+//! `verilog::Verilog`: Export of `Term` graphs as combinational Verilog.
+
+use ::term::{RTerm, TermType, TermGraph};
+use ::sha::Sha256;
+use ::u::U;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write, BufWriter};
+use std::path::Path;
+
+/// A combinational Verilog module computing some output bits from the
+/// `Symbol`s registered as inputs.
+///
+/// The module has the ports `input [N-1:0] message` and `output [M-1:0] digest`.
+/// Input `k` (in order of `add_input()`) is `message[N-1-k]` and output `i`
+/// is `digest[M-1-i]`, so the first bits are the most significant ones and
+/// literals read like the message and hash in hex. Each shared gate becomes
+/// one `wire n<index>`. Without inputs, e.g. for the empty message, the
+/// module has no `message` port.
+#[derive(Clone, Debug)]
+pub struct Verilog {
+    name : String,
+    /// Input bit position and `Symbol`.
+    inputs : Vec<(usize, RTerm)>,
+    outputs : Vec<RTerm>,
+}
+
+impl Verilog {
+
+    /// An empty module named `name`.
+    pub fn new(name : &str) -> Verilog {
+        Verilog { name : name.to_string(), inputs : Vec::new(), outputs : Vec::new() }
+    }
+
+    /// A module `sha256` for the digest of `sha`. The `Symbol`s in `sha.data`
+    /// are the inputs, registered with their position in the message (0 being
+    /// the MSBit of the first byte), and the digest bits are the outputs in the
+    /// order of `Sha256::evaluate()`.
+    pub fn sha(sha : &Sha256) -> Verilog {
+        let mut v = Verilog::new("sha256");
+        for (w, u) in sha.data.iter().enumerate() {
+            for (j, b) in u.bits().iter().rev().enumerate() {
+                if let TermType::Symbol(_) = b.term_type() {
+                    v.add_input(w * 32 + j, *b);
+                }
+            }
+        }
+        for u in sha.digest.iter() {
+            for b in u.bits().iter().rev() {
+                v.add_output(*b);
+            }
+        }
+        v
+    }
+
+    /// Registers the `Symbol` `t` as the next input bit, which is bit
+    /// `position` of the message.
+    pub fn add_input(&mut self, position : usize, t : RTerm) {
+        if let TermType::Symbol(_) = t.term_type() {
+            self.inputs.push((position, t));
+        } else {
+            panic!("Input is not a symbol.");
+        }
+    }
+
+    /// Registers `t` as the next output bit.
+    pub fn add_output(&mut self, t : RTerm) {
+        self.outputs.push(t);
+    }
+
+    /// Name of the module.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Writes the module.
+    ///
+    /// Fails with `InvalidInput` if there are no outputs or if the outputs
+    /// depend on a `Symbol` that is not an input.
+    pub fn write_module<W : Write>(&self, out : &mut W) -> io::Result<()> {
+        let (n, m) = (self.inputs.len(), self.nr_of_outputs()?);
+        let ports : HashMap<RTerm, usize> = self.inputs.iter().enumerate().map(|(k, &(_, t))| (t, n - 1 - k)).collect();
+        if n == 0 {
+            writeln!(out, "module {}(digest);", self.name)?;
+        } else {
+            writeln!(out, "module {}(message, digest);", self.name)?;
+            writeln!(out, "  input [{}:0] message;", n - 1)?;
+        }
+        writeln!(out, "  output [{}:0] digest;", m - 1)?;
+        TermGraph::with(|g| {
+            let name = |t : RTerm| match g.term_type(t) {
+                TermType::Symbol(_) => format!("message[{}]", ports[&t]),
+                TermType::Constant(c) => format!("1'b{}", c as u8),
+                _ => format!("n{}", t.index()),
+            };
+            let needed = g.cone(&self.outputs);
+            for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+                let t = g.id(i);
+                let expr = match g.term_type(t) {
+                    TermType::Symbol(_) if ports.contains_key(&t) => continue,
+                    TermType::Symbol(s) => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("Symbol {} is not an input.", s))),
+                    TermType::Constant(_) => continue,
+                    TermType::Xor(x, y) => format!("{} ^ {}", name(x), name(y)),
+                    TermType::And(x, y) => format!("{} & {}", name(x), name(y)),
+                    TermType::Or(x, y) => format!("{} | {}", name(x), name(y)),
+                    TermType::Not(x) => format!("~{}", name(x)),
+                };
+                writeln!(out, "  wire n{};", i)?;
+                writeln!(out, "  assign n{} = {};", i, expr)?;
+            }
+            for (i, t) in self.outputs.iter().enumerate() {
+                writeln!(out, "  assign digest[{}] = {};", m - 1 - i, name(*t))?;
+            }
+            Ok(())
+        })?;
+        writeln!(out, "endmodule")
+    }
+
+    /// Writes a testbench module `<name>_tb` that applies each message of
+    /// `vectors` and compares the digest with the expected hex string.
+    /// Message bits that are not inputs are ignored. It prints `PASS` or
+    /// the number of failed vectors and finishes the simulation.
+    ///
+    /// Fails with `InvalidInput` if there are no outputs.
+    pub fn write_testbench<W : Write>(&self, out : &mut W, vectors : &[(&[u8], &str)]) -> io::Result<()> {
+        let (n, m) = (self.inputs.len(), self.nr_of_outputs()?);
+        writeln!(out, "`timescale 1ns/1ps")?;
+        writeln!(out, "module {}_tb;", self.name)?;
+        if n > 0 {
+            writeln!(out, "  reg [{}:0] message;", n - 1)?;
+        }
+        writeln!(out, "  wire [{}:0] digest;", m - 1)?;
+        writeln!(out, "  integer errors;")?;
+        if n > 0 {
+            writeln!(out, "  {} dut(.message(message), .digest(digest));", self.name)?;
+        } else {
+            writeln!(out, "  {} dut(.digest(digest));", self.name)?;
+        }
+        writeln!(out, "  initial begin")?;
+        writeln!(out, "    errors = 0;")?;
+        for &(bytes, hex) in vectors.iter() {
+            if n > 0 {
+                let bits : String = self.inputs.iter().map(|&(pos, _)| {
+                    match bytes.get(pos / 8) {
+                        Some(b) if b >> (7 - pos % 8) & 1 == 1 => '1',
+                        _ => '0',
+                    }
+                }).collect();
+                writeln!(out, "    message = {}'b{};", n, bits)?;
+            }
+            writeln!(out, "    #1;")?;
+            writeln!(out, "    if (digest !== {}'h{}) begin", m, hex)?;
+            if n > 0 {
+                writeln!(out, "      $display(\"FAIL %h: %h\", message, digest);")?;
+            } else {
+                writeln!(out, "      $display(\"FAIL %h\", digest);")?;
+            }
+            writeln!(out, "      errors = errors + 1;")?;
+            writeln!(out, "    end")?;
+        }
+        writeln!(out, "    if (errors == 0) $display(\"PASS\");")?;
+        writeln!(out, "    else $display(\"%0d FAILED\", errors);")?;
+        writeln!(out, "    $finish;")?;
+        writeln!(out, "  end")?;
+        writeln!(out, "endmodule")
+    }
+
+    /// The number of outputs, an `InvalidInput` error if there are none.
+    fn nr_of_outputs(&self) -> io::Result<usize> {
+        match self.outputs.len() {
+            0 => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Module {} has no outputs.", self.name))),
+            m => Ok(m),
+        }
+    }
+
+    /// Writes the module to `dir/<name>.v` and the testbench to `dir/<name>_tb.v`.
+    pub fn save(&self, dir : &Path, vectors : &[(&[u8], &str)]) -> io::Result<()> {
+        let mut module = BufWriter::new(File::create(dir.join(format!("{}.v", self.name)))?);
+        self.write_module(&mut module)?;
+        let mut tb = BufWriter::new(File::create(dir.join(format!("{}_tb.v", self.name)))?);
+        self.write_testbench(&mut tb, vectors)
+    }
+}
+
+/// The messages and digests of the tests in `sha.rs`.
+pub const SHA_VECTORS : [(&[u8], &str); 5] = [
+    (b"", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+    (b"a\n", "87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7"),
+    (b"a", "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb"),
+    (b"abcdefghijklmnopabcdefghijklmnopabcdefghijklmnopabcdefghijklm",
+     "4ec58b2ea3a686034907a0b6634076c289bca15fdeb70acd130f804a340143be"),
+    (b"abcdefghijklmnopabcdefghijklmnopabcdefghijklmnopabcdefghijklmnop",
+     "6679a7e0f1319c73d2f2444551c2730d796fb46e6cf5b349781c730ff9644d65"),
+];
+
+/// Saves a module `sha256_<len>` and its testbench for each message length
+/// in `vectors`, since the padding makes the circuit depend on the length.
+/// Each testbench gets the vectors of its length. The module for the empty
+/// message has no inputs.
+///
+/// Returns the names of the modules.
+pub fn save_sha_testbenches(dir : &Path, vectors : &[(&[u8], &str)]) -> io::Result<Vec<String>> {
+    let mut lengths : Vec<usize> = vectors.iter().map(|&(bytes, _)| bytes.len()).collect();
+    lengths.sort();
+    lengths.dedup();
+    let mut names = Vec::new();
+    for len in lengths {
+        let data : Vec<U> = (0..len.div_ceil(4)).map(|_| U::new_symbolic()).collect();
+        let s = Sha256::new(data, match len % 4 { 0 => 32, r => r * 8 });
+        let mut v = Verilog::sha(&s);
+        v.name = format!("sha256_{}", len);
+        let vectors : Vec<(&[u8], &str)> = vectors.iter().filter(|&&(bytes, _)| bytes.len() == len).cloned().collect();
+        v.save(dir, &vectors)?;
+        names.push(v.name);
+    }
+    Ok(names)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::util::{dehex, u32_to_f64bits};
+    use std::collections::HashMap;
+
+    /// Simulates the assignments of a module written by `write_module()`.
+    fn simulate(module : &str, message : &[bool]) -> Vec<bool> {
+        let mut nets : HashMap<String, bool> = HashMap::new();
+        let n = message.len();
+        for (k, b) in message.iter().enumerate() {
+            nets.insert(format!("message[{}]", n - 1 - k), *b);
+        }
+        nets.insert("1'b0".to_string(), false);
+        nets.insert("1'b1".to_string(), true);
+        let mut digest = Vec::new();
+        for line in module.lines().filter(|l| l.starts_with("  assign ")) {
+            let line = line.trim_start_matches("  assign ").trim_end_matches(';');
+            let (lhs, rhs) = line.split_at(line.find(" = ").unwrap());
+            let rhs : Vec<&str> = rhs[3..].split(' ').collect();
+            let v = match rhs.len() {
+                1 if rhs[0].starts_with('~') => !nets[&rhs[0][1..]],
+                1 => nets[rhs[0]],
+                _ => match rhs[1] {
+                    "^" => nets[rhs[0]] ^ nets[rhs[2]],
+                    "&" => nets[rhs[0]] & nets[rhs[2]],
+                    "|" => nets[rhs[0]] | nets[rhs[2]],
+                    op => panic!("{}", op),
+                },
+            };
+            if lhs.starts_with("digest[") {
+                digest.push(v);
+            }
+            nets.insert(lhs.to_string(), v);
+        }
+        digest
+    }
+
+    #[test]
+    fn sha_module() {
+        let data = vec![U::new_symbolic()];
+        let s = Sha256::new(data, 16);
+        let v = Verilog::sha(&s);
+        let mut module = Vec::new();
+        v.write_module(&mut module).unwrap();
+        let module = String::from_utf8(module).unwrap();
+        assert!(module.starts_with("module sha256(message, digest);\n  input [15:0] message;\n  output [255:0] digest;\n"));
+        let gates = TermGraph::with(|g| {
+            let needed = g.cone(&v.outputs);
+            needed.iter().enumerate().filter(|&(i, n)| {
+                *n && !matches!(g.term_type(g.id(i)), TermType::Symbol(_) | TermType::Constant(_))
+            }).count()
+        });
+        assert_eq!(module.matches("  wire ").count(), gates);
+
+        // echo 'a' | sha256sum (see sha.rs) and echo -n 'zz' | sha256sum
+        let vectors : [(&[u8], &str); 2] = [
+            (b"a\n", "87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7"),
+            (b"zz", "4a60bf7d4bc1e485744cf7e8d0860524752fca1ce42331be7c439fd23043f151"),
+        ];
+        for &(bytes, hex) in vectors.iter() {
+            let message : Vec<bool> = (0..16).map(|pos| bytes[pos / 8] >> (7 - pos % 8) & 1 == 1).collect();
+            let expected : Vec<bool> = u32_to_f64bits(dehex(hex).as_ref()).iter().map(|b| *b == 1.).collect();
+            assert_eq!(simulate(&module, &message), expected);
+        }
+
+        let mut tb = Vec::new();
+        v.write_testbench(&mut tb, &vectors).unwrap();
+        let tb = String::from_utf8(tb).unwrap();
+        assert!(tb.contains("  sha256 dut(.message(message), .digest(digest));\n"));
+        assert!(tb.contains("    message = 16'b0110000100001010;\n"));
+        assert!(tb.contains("    if (digest !== 256'h87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7) begin\n"));
+    }
+
+    #[test]
+    fn sha_testbenches() {
+        let dir = ::std::env::temp_dir().join(format!("mostinefficientsha_verilog_{}", ::std::process::id()));
+        ::std::fs::create_dir_all(&dir).unwrap();
+        let names = save_sha_testbenches(&dir, &SHA_VECTORS).unwrap();
+        assert_eq!(names, vec!["sha256_0", "sha256_1", "sha256_2", "sha256_61", "sha256_64"]);
+        for &(bytes, hex) in SHA_VECTORS.iter() {
+            let name = format!("sha256_{}", bytes.len());
+            let module = ::std::fs::read_to_string(dir.join(format!("{}.v", name))).unwrap();
+            let tb = ::std::fs::read_to_string(dir.join(format!("{}_tb.v", name))).unwrap();
+            let n = bytes.len() * 8;
+            if n == 0 {
+                assert!(module.starts_with(&format!("module {}(digest);\n  output [255:0] digest;\n", name)));
+                assert!(!tb.contains("message"));
+                assert!(tb.contains(&format!("  {} dut(.digest(digest));\n", name)));
+            } else {
+                assert!(module.starts_with(&format!("module {}(message, digest);\n  input [{}:0] message;\n", name, n - 1)));
+                assert!(tb.contains(&format!("  {} dut(.message(message), .digest(digest));\n", name)));
+                assert_eq!(tb.matches("    message = ").count(), 1);
+            }
+            assert!(tb.contains(&format!("    if (digest !== 256'h{}) begin\n", hex)));
+            let message : Vec<bool> = (0..n).map(|pos| bytes[pos / 8] >> (7 - pos % 8) & 1 == 1).collect();
+            let expected : Vec<bool> = u32_to_f64bits(dehex(hex).as_ref()).iter().map(|b| *b == 1.).collect();
+            assert_eq!(simulate(&module, &message), expected);
+        }
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn symbol_not_an_input() {
+        let data = vec![U::new_symbolic()];
+        let s = Sha256::new(data, 8);
+        let mut v = Verilog::new("partial");
+        v.add_output(s.digest[0].bits()[0]);
+        assert!(v.write_module(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn no_outputs() {
+        let v = Verilog::new("empty");
+        assert_eq!(v.write_module(&mut Vec::new()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(v.write_testbench(&mut Vec::new(), &[]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,385 @@
+//! `word::WordGraph`: Word-level provenance of `U`s.
+
+use ::term::TermGraph;
+use ::u::U;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// The 32 bit operation that produced a `U`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum WordOp {
+    /// A `U` of 32 fresh `Symbol`s. The number is the index of the input
+    /// word, see `WordGraph::input_symbols()`.
+    Input(usize),
+    Const(u32),
+    Xor(WordId, WordId),
+    And(WordId, WordId),
+    Not(WordId),
+    Add(WordId, WordId),
+    RotateRight(WordId, usize),
+    ShiftRight(WordId, usize),
+}
+
+/// Index of a word-level expression within the `WordGraph` of the current thread.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct WordId(u32);
+
+/// Arena of the word-level expressions of a thread, next to its `TermGraph`.
+///
+/// Each `U` built by `U`s operations remembers the operation (see `U::word()`),
+/// so the word structure of a computation is kept even though the
+/// bits are lowered to `Term`s right away. Like the `TermGraph`, the graph is
+/// hash-consed and topologically ordered.
+///
+/// The expressions are recorded as they are built. `simplify()` folds
+/// constants and applies word-level identities afterwards, `write()` prints
+/// a cone as a listing and `bit_blast()` lowers expressions to `U`s again.
+pub struct WordGraph {
+    nodes : Vec<WordOp>,
+    unique : HashMap<WordOp, WordId>,
+    /// The `Symbol`s of each `Input`, LSBit first.
+    inputs : Vec<[usize ; 32]>,
+}
+
+thread_local!(static WORDS : RefCell<WordGraph> = RefCell::new(WordGraph {
+    nodes : Vec::new(),
+    unique : HashMap::new(),
+    inputs : Vec::new(),
+}));
+
+impl WordId {
+    /// Position of the expression in the node vector of its `WordGraph`.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    /// The operation behind this id.
+    pub fn op(self) -> WordOp {
+        WordGraph::with(|w| w.op(self))
+    }
+
+    /// Evaluates the expression with `u32` arithmetic. `Input`s are assembled
+    /// from the rounded values of their `Symbol`s.
+    ///
+    /// Panics if a `Symbol` is unset.
+    pub fn eval_to_u32(self) -> u32 {
+        let values = WordGraph::with(|w| {
+            TermGraph::with(|g| w.evaluate(&[self], |s| g.symbol_value(s).expect("Symbol not set. Eval failed.") >= 0.5))
+        });
+        values[0]
+    }
+}
+
+impl WordGraph {
+
+    /// Runs `f` with the `WordGraph` of the current thread.
+    pub fn with<F, R>(f : F) -> R where F : FnOnce(&WordGraph) -> R {
+        WORDS.with(|w| f(&w.borrow()))
+    }
+
+    /// Runs `f` with mutable access to the `WordGraph` of the current thread.
+    pub fn with_mut<F, R>(f : F) -> R where F : FnOnce(&mut WordGraph) -> R {
+        WORDS.with(|w| f(&mut w.borrow_mut()))
+    }
+
+    /// Number of expressions in this graph.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// True if no `U` has been created yet.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns the handle of the expression at position `index`.
+    pub fn id(&self, index : usize) -> WordId {
+        assert!(index < self.nodes.len());
+        WordId(index as u32)
+    }
+
+    /// Returns the operation behind `id`.
+    pub fn op(&self, id : WordId) -> WordOp {
+        self.nodes[id.index()]
+    }
+
+    /// Creates a new `Input` word made of the `Symbol`s `symbols`, LSBit first.
+    pub fn input(&mut self, symbols : [usize ; 32]) -> WordId {
+        self.inputs.push(symbols);
+        self.node(WordOp::Input(self.inputs.len() - 1))
+    }
+
+    /// The `Symbol`s of the `Input` word number `n`, LSBit first.
+    pub fn input_symbols(&self, n : usize) -> &[usize ; 32] {
+        &self.inputs[n]
+    }
+
+    /// Returns the expression for `op`, creating it if it does not exist yet.
+    /// The operands of commutative operations are sorted first.
+    pub fn node(&mut self, op : WordOp) -> WordId {
+        let op = match op {
+            WordOp::Xor(a, b) if b < a => WordOp::Xor(b, a),
+            WordOp::And(a, b) if b < a => WordOp::And(b, a),
+            WordOp::Add(a, b) if b < a => WordOp::Add(b, a),
+            op => op,
+        };
+        if let Some(id) = self.unique.get(&op) {
+            return *id;
+        }
+        assert!(self.nodes.len() < u32::MAX as usize, "WordGraph is full.");
+        let id = WordId(self.nodes.len() as u32);
+        self.nodes.push(op);
+        self.unique.insert(op, id);
+        id
+    }
+
+    /// Marks all expressions that `roots` depend on, including the roots.
+    /// Indexed by `WordId::index()`.
+    pub fn cone(&self, roots : &[WordId]) -> Vec<bool> {
+        let len = roots.iter().max().map_or(0, |r| r.index() + 1);
+        let mut needed = vec![false; len];
+        for r in roots {
+            needed[r.index()] = true;
+        }
+        for i in (0..len).rev() {
+            if needed[i] {
+                for o in operands(self.nodes[i]) {
+                    needed[o.index()] = true;
+                }
+            }
+        }
+        needed
+    }
+
+    /// Rebuilds the expressions that `roots` depend on bottom up, folding
+    /// constants and applying `a ^ a = 0`, `a & a = a`, `!!a = a`, the
+    /// identities of 0 and `!0`, and merging nested rotations.
+    ///
+    /// Returns the new roots (in the same order). The old expressions stay valid.
+    pub fn simplify(&mut self, roots : &[WordId]) -> Vec<WordId> {
+        let needed = self.cone(roots);
+        let mut map : Vec<WordId> = Vec::with_capacity(needed.len());
+        for (i, &needed) in needed.iter().enumerate() {
+            let m = |a : WordId| map[a.index()];
+            let op = match self.nodes[i] {
+                _ if !needed => None,
+                WordOp::Input(_) | WordOp::Const(_) => None,
+                WordOp::Xor(a, b) => Some(WordOp::Xor(m(a), m(b))),
+                WordOp::And(a, b) => Some(WordOp::And(m(a), m(b))),
+                WordOp::Add(a, b) => Some(WordOp::Add(m(a), m(b))),
+                WordOp::Not(a) => Some(WordOp::Not(m(a))),
+                WordOp::RotateRight(a, x) => Some(WordOp::RotateRight(m(a), x)),
+                WordOp::ShiftRight(a, x) => Some(WordOp::ShiftRight(m(a), x)),
+            };
+            let new = match op {
+                Some(op) => self.fold(op),
+                None => WordId(i as u32),
+            };
+            map.push(new);
+        }
+        roots.iter().map(|r| map[r.index()]).collect()
+    }
+
+    /// Returns the expression for `op`, simplified with the rules of `simplify()`.
+    fn fold(&mut self, op : WordOp) -> WordId {
+        let c = |g : &WordGraph, a : WordId| match g.nodes[a.index()] {
+            WordOp::Const(c) => Some(c),
+            _ => None,
+        };
+        let op = match op {
+            WordOp::Xor(a, b) if a == b => WordOp::Const(0),
+            WordOp::Xor(a, b) => match (c(self, a), c(self, b)) {
+                (Some(x), Some(y)) => WordOp::Const(x ^ y),
+                (Some(0), _) => return b,
+                (_, Some(0)) => return a,
+                _ => op,
+            },
+            WordOp::And(a, b) if a == b => return a,
+            WordOp::And(a, b) => match (c(self, a), c(self, b)) {
+                (Some(x), Some(y)) => WordOp::Const(x & y),
+                (Some(0), _) | (_, Some(0)) => WordOp::Const(0),
+                (Some(0xffff_ffff), _) => return b,
+                (_, Some(0xffff_ffff)) => return a,
+                _ => op,
+            },
+            WordOp::Add(a, b) => match (c(self, a), c(self, b)) {
+                (Some(x), Some(y)) => WordOp::Const(x.wrapping_add(y)),
+                (Some(0), _) => return b,
+                (_, Some(0)) => return a,
+                _ => op,
+            },
+            WordOp::Not(a) => match self.nodes[a.index()] {
+                WordOp::Const(x) => WordOp::Const(!x),
+                WordOp::Not(b) => return b,
+                _ => op,
+            },
+            WordOp::RotateRight(a, x) if x % 32 == 0 => return a,
+            WordOp::RotateRight(a, x) => match self.nodes[a.index()] {
+                WordOp::Const(y) => WordOp::Const(y.rotate_right(x as u32)),
+                WordOp::RotateRight(b, y) => return self.fold(WordOp::RotateRight(b, (x + y) % 32)),
+                _ => op,
+            },
+            WordOp::ShiftRight(a, 0) => return a,
+            WordOp::ShiftRight(a, x) => match self.nodes[a.index()] {
+                WordOp::Const(y) => WordOp::Const(y >> x),
+                _ => op,
+            },
+            op => op,
+        };
+        self.node(op)
+    }
+
+    /// Writes one line per operation that `roots` depend on, like
+    /// `w12 = w7 + w11`, and one line `out<k> = ...` per root. `Input`s are
+    /// named `in<n>` with their number, rotations are written as `>>>`.
+    pub fn write<W : Write>(&self, roots : &[WordId], out : &mut W) -> io::Result<()> {
+        let name = |a : WordId| match self.nodes[a.index()] {
+            WordOp::Input(n) => format!("in{}", n),
+            WordOp::Const(c) => format!("0x{:08x}", c),
+            _ => format!("w{}", a.index()),
+        };
+        let needed = self.cone(roots);
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            let expr = match self.nodes[i] {
+                WordOp::Input(_) | WordOp::Const(_) => continue,
+                WordOp::Xor(a, b) => format!("{} ^ {}", name(a), name(b)),
+                WordOp::And(a, b) => format!("{} & {}", name(a), name(b)),
+                WordOp::Add(a, b) => format!("{} + {}", name(a), name(b)),
+                WordOp::Not(a) => format!("!{}", name(a)),
+                WordOp::RotateRight(a, x) => format!("{} >>> {}", name(a), x),
+                WordOp::ShiftRight(a, x) => format!("{} >> {}", name(a), x),
+            };
+            writeln!(out, "w{} = {}", i, expr)?;
+        }
+        for (k, r) in roots.iter().enumerate() {
+            writeln!(out, "out{} = {}", k, name(*r))?;
+        }
+        Ok(())
+    }
+
+    /// Lowers `roots` to `Term`s with the operations of `U`, one `U` per root.
+    /// `Input`s reuse the `Symbol`s they have been created with.
+    ///
+    /// Uses the `WordGraph` and `TermGraph` of the current thread, so it must
+    /// not be called from within `with()`.
+    pub fn bit_blast(roots : &[WordId]) -> Vec<U> {
+        let (needed, ops) = WordGraph::with(|w| {
+            let needed = w.cone(roots);
+            let ops : Vec<WordOp> = w.nodes[..needed.len()].to_vec();
+            (needed, ops)
+        });
+        let mut us : Vec<Option<U>> = vec![None; needed.len()];
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            let u = |a : WordId| us[a.index()].as_ref().unwrap();
+            let new = match ops[i] {
+                WordOp::Input(_) => U::from_input(WordId(i as u32)),
+                WordOp::Const(c) => U::from_const(c),
+                WordOp::Xor(a, b) => u(a).xor(u(b)),
+                WordOp::And(a, b) => u(a).and(u(b)),
+                WordOp::Add(a, b) => u(a).add(u(b)),
+                WordOp::Not(a) => u(a).not(),
+                WordOp::RotateRight(a, x) => u(a).rotate_right(x),
+                WordOp::ShiftRight(a, x) => u(a).shift_right(x),
+            };
+            us[i] = Some(new);
+        }
+        roots.iter().map(|r| us[r.index()].clone().unwrap()).collect()
+    }
+
+    /// Evaluates `roots` with `u32` arithmetic. `symbol(s)` gives the value of symbol `s`.
+    pub fn evaluate<F>(&self, roots : &[WordId], symbol : F) -> Vec<u32> where F : Fn(usize) -> bool {
+        let needed = self.cone(roots);
+        let mut values = vec![0u32; needed.len()];
+        for (i, _) in needed.iter().enumerate().filter(|&(_, n)| *n) {
+            values[i] = match self.nodes[i] {
+                WordOp::Input(n) => self.inputs[n].iter().enumerate().fold(0, |v, (b, s)| v | (symbol(*s) as u32) << b),
+                WordOp::Const(c) => c,
+                WordOp::Xor(a, b) => values[a.index()] ^ values[b.index()],
+                WordOp::And(a, b) => values[a.index()] & values[b.index()],
+                WordOp::Not(a) => !values[a.index()],
+                WordOp::Add(a, b) => values[a.index()].wrapping_add(values[b.index()]),
+                WordOp::RotateRight(a, x) => values[a.index()].rotate_right(x as u32),
+                WordOp::ShiftRight(a, x) => values[a.index()] >> x,
+            };
+        }
+        roots.iter().map(|r| values[r.index()]).collect()
+    }
+}
+
+/// The operands of `op`.
+pub fn operands(op : WordOp) -> Vec<WordId> {
+    match op {
+        WordOp::Xor(a, b) | WordOp::And(a, b) | WordOp::Add(a, b) => vec![a, b],
+        WordOp::Not(a) | WordOp::RotateRight(a, _) | WordOp::ShiftRight(a, _) => vec![a],
+        WordOp::Input(_) | WordOp::Const(_) => vec![],
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::u::U;
+
+    #[test]
+    fn provenance_matches_bits() {
+        let u1 = U::new_symbolic();
+        let u2 = U::new_symbolic();
+        u1.set_bytes(b"abcd");
+        u2.set_bytes(b"wxyz");
+        let k : U = 0x428a2f98.into();
+        let r = (&(&u1 + &u2) ^ &u1.rotate_right(7)) + (!&u2 & &k) + (&u1 >> 3);
+        let word = r.word().unwrap();
+        assert_eq!(word.eval_to_u32(), r.eval_to_u32());
+        match word.op() {
+            WordOp::Add(..) => {},
+            op => panic!("{:?}", op),
+        }
+    }
+
+    #[test]
+    fn simplify() {
+        let x = U::new_symbolic();
+        let c : U = 0x8000_0001.into();
+        let zero : U = 0.into();
+        let r = (&(&x ^ &x) + &x).rotate_right(3).rotate_right(29) ^ (!&!&c >> 31);
+        let ones = &(&x & &!&zero) & &x;
+        let roots = [r.word().unwrap(), ones.word().unwrap(), (&c + &c).word().unwrap()];
+        let simplified = WordGraph::with_mut(|w| w.simplify(&roots));
+        assert_eq!(simplified[0].op(), WordOp::Xor(x.word().unwrap(), WordGraph::with_mut(|w| w.node(WordOp::Const(1)))));
+        assert_eq!(simplified[1], x.word().unwrap());
+        assert_eq!(simplified[2].op(), WordOp::Const(2));
+
+        x.set_bytes(b"wxyz");
+        let blasted = WordGraph::bit_blast(&simplified);
+        assert_eq!(blasted[0].eval_to_u32(), r.eval_to_u32());
+        assert_eq!(blasted[0].bits()[1..], x.bits()[1..]);
+        assert_eq!(blasted[1].bits(), x.bits());
+        assert_eq!(blasted[2].eval_to_u32(), 2);
+
+        let mut listing = Vec::new();
+        WordGraph::with(|w| w.write(&roots[..1], &mut listing)).unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+        assert!(listing.contains(" >>> 29\n"));
+        assert!(listing.contains(&format!(" = !0x{:08x}\n", 0x8000_0001u32)));
+    }
+
+    #[test]
+    fn hash_consed() {
+        let u1 = U::new_symbolic();
+        let u2 = U::new_symbolic();
+        assert_eq!((&u1 + &u2).word(), (&u2 + &u1).word());
+        assert_ne!((&u1 ^ &u2).word(), (&u1 + &u2).word());
+        assert_ne!(u1.word(), u2.word());
+        assert!(U::from_input(u2.word().unwrap()) == u2);
+        match u1.word().unwrap().op() {
+            WordOp::Input(n) => assert_eq!(WordGraph::with(|w| w.input_symbols(n).to_vec()),
+                                           u1.bits().iter().map(|b| match b.term_type() {
+                                               ::term::TermType::Symbol(s) => s,
+                                               _ => unreachable!(),
+                                           }).collect::<Vec<_>>()),
+            op => panic!("{:?}", op),
+        }
+    }
+}